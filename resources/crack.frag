@@ -0,0 +1,18 @@
+#version 410
+
+in vec2 frag_uv;        // Pixel within the tile, 0 to 16.
+in float frag_progress; // How far the tile is mined, 0 to 1, in steps.
+
+out vec4 rgba;
+
+void main() {
+    // A few crooked cracks spread out from the middle of the tile as it's mined.
+    vec2 p = floor(frag_uv) + 0.5 - 8.0;
+    float r = length(p);
+    float a = atan(p.y, p.x) / 6.2831853 * 5.0 + 0.15 * sin(r * 1.3);
+    float d = abs(fract(a + 0.5) - 0.5) * 6.2831853 / 5.0 * r; // Distance to the nearest crack.
+    if (d > 0.8 || r > frag_progress * 11.5) {
+        discard;
+    }
+    rgba = vec4(0.0, 0.0, 0.0, 0.6);
+}
@@ -0,0 +1,17 @@
+#version 410
+
+in vec2 vert_xy;
+in vec3 vert_uvp; // Pixel within the tile, and how far the tile is mined.
+
+uniform mat3 view_matrix;
+
+out vec2 frag_uv;
+out float frag_progress;
+
+void main() {
+    vec3 pos = view_matrix * vec3(vert_xy, 1.0);
+    gl_Position = vec4(pos.xy, 0.0, 1.0);
+
+    frag_uv = vert_uvp.xy;
+    frag_progress = vert_uvp.z;
+}
@@ -6,8 +6,7 @@ uniform sampler2D light_map;
 
 out vec4 rgba;
 
+// Drawn with the scene as the destination color, so this tints (multiplies) whatever is under it.
 void main() {
-	vec2 light_map_size = vec2(textureSize(light_map, 0));
-	rgba = texture(light_map, frag_uv);
-	//rgba.a = 0.9;
-}
\ No newline at end of file
+	rgba = vec4(texture(light_map, frag_uv).rgb, 1);
+}
@@ -315,6 +315,12 @@ impl<T> Index<usize> for FastArray2D<T> {
     }
 }
 
+impl<T> IndexMut<usize> for FastArray2D<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.data[index]
+    }
+}
+
 impl<T> Index2d<usize> for FastArray2D<T> {
     fn size(&self) -> (usize, usize) {
         (self.x_mask + 1, self.y_mask + 1)
@@ -0,0 +1,375 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::config::*;
+use super::connect;
+use super::game_update::*;
+use super::input_event::*;
+use super::key_bindings::*;
+use crate::game::humanoid::*;
+use crate::game::inventory::*;
+use crate::game::net::*;
+use crate::time::*;
+
+pub const BOT_USAGE: &str = "\
+Usage: bot [options] [client options]
+  --bots <n>             Number of bots to connect (default 1)
+  --script <path>        Play the input in this file on a loop, instead of random input
+  --duration <s>         Disconnect after this long (default: run until killed)
+  --report <s>           Print stats this often (default 5)
+  --seed <seed>          Seed for random input
+  --help                 Print this message
+
+Client options (--config, --server, --name, --window-size, --timeout, --session-timeout,
+--interp-delay, --bind) work like they do for the client. Bots are named after --name and
+numbered from 1, and the window size is how much of the world each one asks for.
+
+Scripts have one input per line: milliseconds from the start of the loop, then one of
+``down <key>``, ``up <key>`` or ``cursor <x> <y>``. Lines starting with # are skipped, and the
+loop starts over after the last line.";
+
+/// Bot settings, built from command line arguments.
+#[derive(Clone, Debug)]
+pub struct BotConfig {
+    pub client: ClientConfig,
+    pub bots: usize,
+    pub script: Option<Vec<(u64, InputEvent)>>, // Inputs and when (in us into the loop).
+    pub duration_s: Option<u64>,
+    pub report_s: u64,
+    pub seed: u64,
+}
+
+impl BotConfig {
+    /// Builds a config from command line arguments (excluding the program name). Anything that
+    /// isn't a bot option goes to ClientConfig::from_args.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value for {flag}: {value:?}"))
+        }
+
+        let mut config = Self {
+            client: ClientConfig::default(),
+            bots: 1,
+            script: None,
+            duration_s: None,
+            report_s: 5,
+            seed: 1,
+        };
+        let mut client_args = vec![];
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--help" => return Err(String::from(BOT_USAGE)),
+                "--fullscreen" | "--no-vsync" => client_args.push(flag),
+                _ => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for {flag}\n\n{BOT_USAGE}"))?;
+                    match flag.as_str() {
+                        "--bots" => config.bots = parse(&flag, &value)?,
+                        "--script" => {
+                            let text = std::fs::read_to_string(&value)
+                                .map_err(|e| format!("could not read script {value:?}: {e}"))?;
+                            let script = parse_script(&text)
+                                .map_err(|e| format!("invalid script {value:?}: {e}"))?;
+                            config.script = Some(script);
+                        }
+                        "--duration" => config.duration_s = Some(parse(&flag, &value)?),
+                        "--report" => config.report_s = parse(&flag, &value)?,
+                        "--seed" => config.seed = parse(&flag, &value)?,
+                        _ => client_args.extend([flag, value]),
+                    }
+                }
+            }
+        }
+
+        config.client =
+            ClientConfig::from_args(client_args).map_err(|e| e.replace(CLIENT_USAGE, BOT_USAGE))?;
+        if config.bots == 0 || config.report_s == 0 {
+            return Err(String::from("--bots and --report must be at least 1"));
+        }
+        Ok(config)
+    }
+}
+
+/// Reads a bot script. See BOT_USAGE for the format.
+pub fn parse_script(text: &str) -> Result<Vec<(u64, InputEvent)>, String> {
+    let mut script = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {
+            continue;
+        }
+        let error = || format!("line {}: {line:?}", i + 1);
+        let at_ms: u64 = words[0].parse().map_err(|_| error())?;
+        let event = match words[1..] {
+            ["down", key] => InputEvent::KeyEvent(KeyState::Down, parse_key(key)?),
+            ["up", key] => InputEvent::KeyEvent(KeyState::Up, parse_key(key)?),
+            ["cursor", x, y] => InputEvent::CursorMove(
+                x.parse().map_err(|_| error())?,
+                y.parse().map_err(|_| error())?,
+            ),
+            _ => return Err(error()),
+        };
+        if script
+            .last()
+            .map_or(false, |&(last, _)| at_ms * 1_000 < last)
+        {
+            return Err(format!("{}, times must not go backwards", error()));
+        }
+        script.push((at_ms * 1_000, event));
+    }
+    Ok(script)
+}
+
+/// Where a bot's input comes from.
+pub enum BotInput {
+    Random {
+        rng: u64,
+        next_us: u64,          // When to pick what to do next.
+        held: Vec<InputKey>,   // Released before picking again.
+        bindings: KeyBindings, // So the keys pressed match the actions meant.
+    },
+    Script {
+        script: Vec<(u64, InputEvent)>,
+        next: usize,        // Index of the next input.
+        loop_start_us: u64, // When the current loop started.
+    },
+}
+
+impl BotInput {
+    pub fn random(seed: u64, bindings: KeyBindings) -> Self {
+        BotInput::Random {
+            rng: seed | 1,
+            next_us: 0,
+            held: vec![],
+            bindings,
+        }
+    }
+
+    pub fn script(script: Vec<(u64, InputEvent)>) -> Self {
+        BotInput::Script {
+            script,
+            next: 0,
+            loop_start_us: 0,
+        }
+    }
+
+    /// Returns the inputs due by ``now_us`` (time since the bot connected).
+    fn poll(&mut self, now_us: u64, (view_w, view_h): (f32, f32)) -> Vec<InputEvent> {
+        let mut out = vec![];
+        match self {
+            BotInput::Script {
+                script,
+                next,
+                loop_start_us,
+            } => {
+                let Some(&(length_us, _)) = script.last() else {
+                    return out;
+                };
+                loop {
+                    let Some(&(at_us, event)) = script.get(*next) else {
+                        *next = 0;
+                        *loop_start_us += length_us.max(INPUT_FRAMETIME_US);
+                        continue;
+                    };
+                    if *loop_start_us + at_us > now_us {
+                        break;
+                    }
+                    out.push(event);
+                    *next += 1;
+                }
+            }
+            BotInput::Random {
+                rng,
+                next_us,
+                held,
+                bindings,
+            } => {
+                if now_us < *next_us {
+                    return out;
+                }
+
+                // xorshift64, mapped to [0, 1).
+                let mut roll = || {
+                    *rng ^= *rng << 13;
+                    *rng ^= *rng >> 7;
+                    *rng ^= *rng << 17;
+                    (*rng >> 40) as f32 / (1u64 << 24) as f32
+                };
+
+                // Let go of everything, then wander, jump, mine and switch slots at random.
+                let mut press = vec![];
+                match roll() {
+                    r if r < 0.4 => press.push(&bindings.move_left),
+                    r if r < 0.8 => press.push(&bindings.move_right),
+                    _ => {}
+                }
+                if roll() < 0.3 {
+                    press.push(&bindings.jump);
+                }
+                if roll() < 0.2 {
+                    out.push(InputEvent::CursorMove(roll() * view_w, roll() * view_h));
+                    press.push(&bindings.primary);
+                }
+                if roll() < 0.1 {
+                    let slot = (roll() * HOTBAR_SIZE as f32) as usize;
+                    press.push(&bindings.hotbar[slot.min(HOTBAR_SIZE - 1)]);
+                }
+                *next_us = now_us + 250_000 + (roll() * 1_750_000.) as u64;
+
+                for key in held.drain(..) {
+                    out.push(InputEvent::KeyEvent(KeyState::Up, key));
+                }
+                for &key in press.iter().filter_map(|keys| keys.first()) {
+                    out.push(InputEvent::KeyEvent(KeyState::Down, key));
+                    held.push(key);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// What a bot has seen so far. Counts are totals since it connected.
+#[derive(Clone, Debug, Default)]
+pub struct BotStats {
+    pub connected: bool,
+    pub error: Option<String>, // Why it couldn't connect, or stopped.
+    pub frames: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub rtt_us: Option<u64>,
+    pub resent: u64,      // Reliable messages sent again.
+    pub corrections: u64, // Times the server disagreed with the predicted player.
+    pub unacked_inputs: usize,
+}
+
+/// Runs one bot, like the client's update thread minus the rendering, until ``stop`` is set or
+/// the server goes away. Keeps ``stats`` up to date as it goes.
+pub fn run_bot(
+    config: ClientConfig,
+    mut input: BotInput,
+    stats: Arc<Mutex<BotStats>>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut net_events = vec![];
+    let (mut endpoint, server_addr, (world_w, world_h, player_id)) =
+        match connect(&config, &mut net_events) {
+            Ok(v) => v,
+            Err(e) => {
+                stats.lock().unwrap().error = Some(e);
+                return;
+            }
+        };
+    stats.lock().unwrap().connected = true;
+
+    // Create client state.
+    let view = (config.window_w as f32, config.window_h as f32);
+    let mut game_update = GameUpdate::new(
+        view.0,
+        view.1,
+        world_w,
+        world_h,
+        player_id,
+        config.interpolation_delay_ms * 1_000,
+        config.bindings.key_map().unwrap(),
+    );
+    game_update.set_show_debug(false); // Nobody's looking.
+
+    // Time keeping.
+    let frametime = INPUT_FRAMETIME_US;
+    let start = get_microseconds_as_u64();
+    let mut timestamp = start;
+
+    while !stop.load(Ordering::Relaxed) {
+        let next_timestamp = wait(timestamp + frametime);
+
+        // Run preframe. The endpoint forgets the server once it goes quiet for too long.
+        let received = endpoint.recv_from(&mut net_events);
+        if endpoint.channel(server_addr).is_none() {
+            stats.lock().unwrap().error = Some(String::from("server stopped responding"));
+            break;
+        }
+        game_update.preframe(
+            timestamp,
+            input.poll(timestamp - start, view).into_iter(),
+            std::mem::take(&mut net_events)
+                .into_iter()
+                .filter(|&(_, src)| src == server_addr)
+                .map(|(event, _)| event),
+        );
+
+        // Simulate the time between timestamp and next_timestamp.
+        let frames = (next_timestamp - timestamp) / frametime;
+        for _ in 0..frames {
+            game_update.step(timestamp, frametime);
+            timestamp += frametime;
+        }
+
+        // Run postframe, and send net messages.
+        let (frame, net_events) = game_update.postframe(timestamp);
+        let sent = match endpoint.send_to(server_addr, net_events) {
+            Ok(sent) => sent,
+            Err(e) => {
+                stats.lock().unwrap().error = Some(format!("could not send: {e}"));
+                break;
+            }
+        };
+        if frame.is_none() {
+            break;
+        }
+
+        // Update stats.
+        let channel = endpoint.channel(server_addr).unwrap();
+        let mut stats = stats.lock().unwrap();
+        stats.frames += frames;
+        stats.bytes_sent += sent as u64;
+        stats.bytes_received += received as u64;
+        stats.rtt_us = channel.rtt_us();
+        stats.resent = channel.resent();
+        stats.corrections = game_update.corrections();
+        stats.unacked_inputs = game_update.unacked_inputs();
+    }
+
+    // Leave.
+    let _ = endpoint.send_to(server_addr, &[NetEvent::Disconnect]);
+    stats.lock().unwrap().connected = false;
+}
+
+/// Sums up what every bot did between two sets of stats, ``elapsed_us`` apart.
+pub fn report(before: &[BotStats], after: &[BotStats], elapsed_us: u64) -> String {
+    let seconds = elapsed_us.max(1) as f32 / 1_000_000.;
+    let connected = after.iter().filter(|s| s.connected).count();
+    let rtts: Vec<f32> = after
+        .iter()
+        .filter_map(|s| s.rtt_us)
+        .map(|rtt| rtt as f32 / 1_000.)
+        .collect();
+    let rtt_avg = rtts.iter().sum::<f32>() / rtts.len().max(1) as f32;
+    let rtt_max = rtts.iter().copied().fold(0., f32::max);
+    let per_second = |f: fn(&BotStats) -> u64| {
+        let total: u64 = before.iter().zip(after).map(|(b, a)| f(a) - f(b)).sum();
+        total as f32 / seconds
+    };
+    let up_kb = per_second(|s| s.bytes_sent) / 1024.;
+    let down_kb = per_second(|s| s.bytes_received) / 1024.;
+    let resent = per_second(|s| s.resent);
+    let corrections = per_second(|s| s.corrections);
+    let fps = per_second(|s| s.frames) / connected.max(1) as f32;
+    let unacked = after.iter().map(|s| s.unacked_inputs).sum::<usize>() as f32 / after.len() as f32;
+
+    let mut out = format!(
+        "{connected}/{} connected | rtt avg {rtt_avg:.1}ms max {rtt_max:.1}ms | \
+         up {up_kb:.1} KiB/s down {down_kb:.1} KiB/s | resends {resent:.1}/s | \
+         corrections {corrections:.2}/s | unacked inputs {unacked:.1} | {fps:.0} fps",
+        after.len()
+    );
+    if let Some(error) = after.iter().find_map(|s| s.error.as_ref()) {
+        let failed = after.iter().filter(|s| s.error.is_some()).count();
+        out += &format!("\n  {failed} stopped, e.g. {error}");
+    }
+    out
+}
@@ -0,0 +1,197 @@
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+
+use crate::client::key_bindings::*;
+use crate::game::net::*;
+
+pub const CLIENT_USAGE: &str = "\
+Usage: client [options]
+  --config <path>        Load settings from a TOML file (flags override it)
+  --server <host[:port]> Server to connect to
+  --name <name>          Player name
+  --window-size <w>x<h>  Window size
+  --fullscreen           Start in borderless fullscreen
+  --no-vsync             Disable vsync
+  --timeout <ms>         Give up connecting after this long
+  --session-timeout <ms> Disconnect once the server has been silent this long
+  --interp-delay <ms>    How far behind the server other players and items are drawn
+  --bind <action>=<keys> Rebind an action to comma separated keys, like jump=W,Space
+  --help                 Print this message";
+
+const DEFAULT_PORT: u16 = 0xCAFE;
+
+/// Client settings. Loaded from an optional TOML file, then overridden by command line flags.
+/// Every field may be left out of the file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub server_address: String,
+    pub player_name: String,
+    pub window_w: u32,
+    pub window_h: u32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub connect_timeout_ms: u64,
+    pub timeout_ms: u64, // Once connected.
+    pub interpolation_delay_ms: u64,
+    pub bindings: KeyBindings,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            server_address: format!("127.0.0.1:{DEFAULT_PORT}"),
+            player_name: String::from("Player"),
+            window_w: 1080,
+            window_h: 720,
+            fullscreen: false,
+            vsync: true,
+            connect_timeout_ms: 5_000,
+            timeout_ms: 5_000,
+            interpolation_delay_ms: 150,
+            bindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Builds a config from command line arguments (excluding the program name).
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let mut config = None;
+        let mut overrides = vec![];
+
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--help" => return Err(String::from(CLIENT_USAGE)),
+                "--fullscreen" | "--no-vsync" => overrides.push((flag, String::new())),
+                _ => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for {flag}\n\n{CLIENT_USAGE}"))?;
+                    match flag.as_str() {
+                        "--config" => config = Some(PathBuf::from(value)),
+                        _ => overrides.push((flag, value)),
+                    }
+                }
+            }
+        }
+
+        // Load the file first so flags take priority.
+        let mut out = match config {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        for (flag, value) in overrides {
+            out.apply_flag(&flag, &value)?;
+        }
+
+        out.validate()?;
+        Ok(out)
+    }
+
+    pub fn from_file(path: &std::path::Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read config {path:?}: {e}"))?;
+        toml::from_str(&text).map_err(|e| format!("could not parse config {path:?}: {e}"))
+    }
+
+    fn apply_flag(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value for {flag}: {value:?}"))
+        }
+
+        match flag {
+            "--server" => self.server_address = String::from(value),
+            "--name" => self.player_name = String::from(value),
+            "--window-size" => {
+                let (w, h) = value
+                    .split_once('x')
+                    .ok_or_else(|| format!("invalid value for {flag}: {value:?}"))?;
+                self.window_w = parse(flag, w)?;
+                self.window_h = parse(flag, h)?;
+            }
+            "--fullscreen" => self.fullscreen = true,
+            "--no-vsync" => self.vsync = false,
+            "--timeout" => self.connect_timeout_ms = parse(flag, value)?,
+            "--session-timeout" => self.timeout_ms = parse(flag, value)?,
+            "--interp-delay" => self.interpolation_delay_ms = parse(flag, value)?,
+            "--bind" => {
+                let (action, keys) = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid value for {flag}: {value:?}"))?;
+                let keys = keys
+                    .split(',')
+                    .filter(|key| !key.is_empty())
+                    .map(parse_key)
+                    .collect::<Result<_, _>>()?;
+                self.bindings.rebind(action, keys)?;
+            }
+            _ => return Err(format!("unknown option {flag}\n\n{CLIENT_USAGE}")),
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !is_valid_name(&self.player_name) {
+            return Err(format!(
+                "invalid player name: {}",
+                RejectReason::InvalidName
+            ));
+        }
+        if self.window_w < 64 || self.window_h < 64 {
+            return Err(String::from("window must be at least 64x64"));
+        }
+        self.bindings.key_map()?;
+        Ok(())
+    }
+
+    /// Resolves ``server_address``, using the default port if none is given.
+    pub fn resolve_server(&self) -> Result<SocketAddr, String> {
+        // IP addresses are parsed directly, since an IPv6 one like "::1" has colons but no port.
+        let address = &self.server_address;
+        if let Ok(addr) = address.parse::<SocketAddr>() {
+            return Ok(addr);
+        }
+        let ip = address.strip_prefix('[').and_then(|a| a.strip_suffix(']'));
+        if let Ok(ip) = ip.unwrap_or(address).parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, DEFAULT_PORT));
+        }
+
+        // Otherwise it's a host name, with or without a port.
+        let address = match address.contains(':') {
+            true => address.clone(),
+            false => format!("{address}:{DEFAULT_PORT}"),
+        };
+        address
+            .to_socket_addrs()
+            .map_err(|e| format!("could not resolve server {address:?}: {e}"))?
+            .next()
+            .ok_or_else(|| format!("could not resolve server {address:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_ip_addresses_with_and_without_ports() {
+        let resolve = |address: &str| {
+            let config = ClientConfig {
+                server_address: String::from(address),
+                ..Default::default()
+            };
+            config.resolve_server().unwrap().to_string()
+        };
+        assert_eq!(resolve("127.0.0.1"), format!("127.0.0.1:{DEFAULT_PORT}"));
+        assert_eq!(resolve("127.0.0.1:1234"), "127.0.0.1:1234");
+        assert_eq!(resolve("::1"), format!("[::1]:{DEFAULT_PORT}"));
+        assert_eq!(resolve("[::1]"), format!("[::1]:{DEFAULT_PORT}"));
+        assert_eq!(resolve("[::1]:1234"), "[::1]:1234");
+    }
+}
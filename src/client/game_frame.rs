@@ -1,4 +1,5 @@
 use crate::array2d::*;
+use crate::game::inventory::*;
 use crate::game::item::*;
 use crate::game::tile::*;
 
@@ -6,6 +7,9 @@ pub struct GameFrame {
     // Debug printing.
     pub debug_text: String,
 
+    // Sky.
+    pub sky_color: (f32, f32, f32), // Cleared to before drawing anything.
+
     // View data.
     pub view_x: usize,
     pub view_y: usize,
@@ -15,6 +19,10 @@ pub struct GameFrame {
     // Item layer.
     pub items: Vec<(f32, f32, ItemId)>,
 
+    // Hotbar.
+    pub hotbar: Vec<Option<ItemStack>>, // The whole inventory while it's open.
+    pub selected_slot: usize,
+
     // Humanoid layer.
     pub humanoid_positions: Vec<(f32, f32)>,
 
@@ -23,6 +31,7 @@ pub struct GameFrame {
     pub tiles_y: usize,
     pub foreground_tiles: Array2D<Tile>,
     pub background_tiles: Array2D<Tile>,
+    pub mining: Option<(usize, usize, f32)>, // The tile being mined, and how far along (0 to 1).
 
     // Lighting layer.
     pub light_x: usize,
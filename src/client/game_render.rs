@@ -1,5 +1,7 @@
 use super::game_frame::GameFrame;
 use crate::array2d::*;
+use crate::common::*;
+use crate::game::inventory::*;
 use crate::game::item::*;
 use crate::game::lighting::*;
 use crate::game::tile::*;
@@ -7,6 +9,8 @@ use ezgl::gl;
 use ezgl::{Buffer, Texture2D};
 use std::collections::HashMap;
 
+const CRACK_STAGES: f32 = 4.; // Steps the crack overlay grows in.
+
 pub struct GameRender {
     textures: HashMap<&'static str, ezgl::Texture2D>,
     programs: HashMap<&'static str, ezgl::Program>,
@@ -18,10 +22,20 @@ pub struct GameRender {
     debug_text_xy: Buffer<(f32, f32)>,
     debug_text_uv: Buffer<(f32, f32)>,
 
-    // Tile state data.
+    // Item state data.
     item_xy: Buffer<(f32, f32)>,
     item_uv: Buffer<(f32, f32)>,
 
+    // Crack overlay data.
+    crack_xy: Buffer<(f32, f32)>,
+    crack_uvp: Buffer<(f32, f32, f32)>,
+
+    // Hotbar data.
+    hotbar_xy: Buffer<(f32, f32)>,
+    hotbar_uv: Buffer<(f32, f32)>,
+    hotbar_text_xy: Buffer<(f32, f32)>,
+    hotbar_text_uv: Buffer<(f32, f32)>,
+
     // Humanoid state data.
     humanoid_xy: Buffer<(f32, f32)>,
     humanoid_rgb: Buffer<(f32, f32, f32)>,
@@ -59,6 +73,14 @@ impl GameRender {
             item_xy: Buffer::new(),
             item_uv: Buffer::new(),
 
+            crack_xy: Buffer::new(),
+            crack_uvp: Buffer::new(),
+
+            hotbar_xy: Buffer::new(),
+            hotbar_uv: Buffer::new(),
+            hotbar_text_xy: Buffer::new(),
+            hotbar_text_uv: Buffer::new(),
+
             humanoid_xy: Buffer::new(),
             humanoid_rgb: Buffer::new(),
 
@@ -74,6 +96,12 @@ impl GameRender {
     }
 
     pub unsafe fn render(&mut self, game_frame: &GameFrame) {
+        // Draw to the whole window, which may have been resized.
+        gl::Viewport(0, 0, game_frame.view_w as _, game_frame.view_h as _);
+
+        // Clear to the sky, which changes with the time of day.
+        let (r, g, b) = game_frame.sky_color;
+        gl::ClearColor(r, g, b, 1.);
         gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
         // view calculation
@@ -127,35 +155,6 @@ impl GameRender {
 
         // Item rendering.
         {
-            fn gen_item_buffers(
-                xy: &mut Buffer<(f32, f32)>,
-                uv: &mut Buffer<(f32, f32)>,
-                items: &Vec<(f32, f32, ItemId)>,
-            ) -> u32 {
-                let mut xy_vec = Vec::with_capacity(4 * items.len());
-                let mut uv_vec = Vec::with_capacity(4 * items.len());
-                for (x, y, item_id) in items {
-                    xy_vec.extend_from_slice(&[
-                        (*x, *y),
-                        (*x + 16., *y),
-                        (*x + 16., *y + 16.),
-                        (*x, *y + 16.),
-                    ]);
-                    let (u, v) = match item_id {
-                        ItemId::Dirt => (16., 0.),
-                        ItemId::Stone => (32., 0.),
-                    };
-                    uv_vec.extend_from_slice(&[
-                        (u, v),
-                        (u + 16., v),
-                        (u + 16., v + 16.),
-                        (u, v + 16.),
-                    ]);
-                }
-                xy.init(gl::ARRAY_BUFFER, &xy_vec).unwrap();
-                uv.init(gl::ARRAY_BUFFER, &uv_vec).unwrap();
-                (xy_vec.len() / 4) as u32
-            }
             let item_count =
                 gen_item_buffers(&mut self.item_xy, &mut self.item_uv, &game_frame.items);
 
@@ -163,7 +162,7 @@ impl GameRender {
                 .with_buffer(&self.item_xy, "vert_xy")
                 .with_buffer(&self.item_uv, "vert_uv")
                 .with_uniform(view.as_ref() as &[[f32; 3]; 3], "view_matrix")
-            .enable_blend(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)
+                .enable_blend(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)
                 .with_texture(&self.textures["tile_sheet.png"], "tex")
                 .draw();
         }
@@ -189,6 +188,27 @@ impl GameRender {
             .with_texture(&self.textures["mask_sheet.png"], "mask_sheet")
             .draw();
 
+        // Cracks on the tile being mined.
+        if let Some((x, y, progress)) = game_frame.mining {
+            let stage = (progress * CRACK_STAGES).ceil() / CRACK_STAGES;
+            let (x, y) = ((x * TILE_SIZE) as f32, (y * TILE_SIZE) as f32);
+            let xy = [(x, y), (x + 16., y), (x + 16., y + 16.), (x, y + 16.)];
+            let uvp = [
+                (0., 0., stage),
+                (16., 0., stage),
+                (16., 16., stage),
+                (0., 16., stage),
+            ];
+            self.crack_xy.init(gl::ARRAY_BUFFER, &xy).unwrap();
+            self.crack_uvp.init(gl::ARRAY_BUFFER, &uvp).unwrap();
+            ezgl::Draw::start_tri_draw(2, &self.programs["crack"], &self.ibo)
+                .with_buffer(&self.crack_xy, "vert_xy")
+                .with_buffer(&self.crack_uvp, "vert_uvp")
+                .with_uniform(view.as_ref() as &[[f32; 3]; 3], "view_matrix")
+                .enable_blend(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)
+                .draw();
+        }
+
         // Fill light buffers with data.
         gen_light_buffers(
             &mut self.light_xy,
@@ -196,11 +216,14 @@ impl GameRender {
             &mut self.light_tex,
             game_frame.light_x,
             game_frame.light_y,
-            &game_frame.light_map_r,
-            (255, 0, 0),
+            (
+                &game_frame.light_map_r,
+                &game_frame.light_map_g,
+                &game_frame.light_map_b,
+            ),
         );
 
-        // Render light map.
+        // Render light map, multiplying it over everything drawn so far.
         unsafe {
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
@@ -210,6 +233,39 @@ impl GameRender {
             .with_buffer(&self.light_uv, "vert_uv")
             .with_uniform(view.as_ref() as &[[f32; 3]; 3], "view_matrix")
             .with_texture(&self.light_tex, "light_map")
+            .enable_blend(gl::DST_COLOR, gl::ZERO)
+            .draw();
+
+        // Hotbar.
+        let (icons, text, text_xy) = layout_hotbar(
+            &game_frame.hotbar,
+            game_frame.selected_slot,
+            (
+                game_frame.view_x as f32,
+                game_frame.view_y as f32,
+                game_frame.view_w as f32,
+                game_frame.view_h as f32,
+            ),
+        );
+        let icon_count = gen_item_buffers(&mut self.hotbar_xy, &mut self.hotbar_uv, &icons);
+        ezgl::Draw::start_tri_draw(icon_count * 2, &self.programs["quad"], &self.ibo)
+            .with_buffer(&self.hotbar_xy, "vert_xy")
+            .with_buffer(&self.hotbar_uv, "vert_uv")
+            .with_uniform(view.as_ref() as &[[f32; 3]; 3], "view_matrix")
+            .enable_blend(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)
+            .with_texture(&self.textures["tile_sheet.png"], "tex")
+            .draw();
+        let char_count = gen_debug_text_buffers(
+            &mut self.hotbar_text_xy,
+            &mut self.hotbar_text_uv,
+            &text,
+            text_xy,
+        );
+        ezgl::Draw::start_tri_draw(char_count as u32 / 2, &self.programs["quad"], &self.ibo)
+            .with_buffer(&self.hotbar_text_xy, "vert_xy")
+            .with_buffer(&self.hotbar_text_uv, "vert_uv")
+            .with_uniform(view.as_ref() as &[[f32; 3]; 3], "view_matrix")
+            .with_texture(&self.textures["debug_font.png"], "tex")
             .enable_blend(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)
             .draw();
 
@@ -251,7 +307,7 @@ fn load_game_textures() -> HashMap<&'static str, ezgl::Texture2D> {
 
 fn load_game_programs() -> HashMap<&'static str, ezgl::Program> {
     let root = crate::io::get_root().join("resources");
-    let load_list = ["fg_tile", "bg_tile", "light", "quad"];
+    let load_list = ["fg_tile", "bg_tile", "light", "quad", "crack"];
     let mut hmap = HashMap::new();
 
     for string in load_list {
@@ -289,15 +345,13 @@ pub fn gen_tile_buffers(
         for x in 1..tiles_w - 1 {
             let id = *tiles.get(x, y).unwrap();
 
-            // Get tile UV (skip None tiles).
-            let (u, v) = match id {
-                Tile::None => continue,
-                Tile::Stone => (32, 0),
-                Tile::Dirt => (16, 0),
+            // Get tile UV (skip tiles without one).
+            let Some((u, v)) = id.properties().uv else {
+                continue;
             };
 
             // Convert tile ID to f32.
-            let id = id as u8;
+            let id = id.0;
 
             // Caluclate xyz.
             let tile_x = ((x + tiles_x) * 16) as f32; // In pixels.
@@ -319,14 +373,14 @@ pub fn gen_tile_buffers(
             ]);
 
             // Calculate mask uv.
-            let t = *tiles.get(x, y - 1).unwrap() as u8;
-            let tr = *tiles.get(x + 1, y - 1).unwrap() as u8;
-            let r = *tiles.get(x + 1, y).unwrap() as u8;
-            let br = *tiles.get(x + 1, y + 1).unwrap() as u8;
-            let b = *tiles.get(x, y + 1).unwrap() as u8;
-            let bl = *tiles.get(x - 1, y + 1).unwrap() as u8;
-            let l = *tiles.get(x - 1, y).unwrap() as u8;
-            let tl = *tiles.get(x - 1, y - 1).unwrap() as u8;
+            let t = tiles.get(x, y - 1).unwrap().0;
+            let tr = tiles.get(x + 1, y - 1).unwrap().0;
+            let r = tiles.get(x + 1, y).unwrap().0;
+            let br = tiles.get(x + 1, y + 1).unwrap().0;
+            let b = tiles.get(x, y + 1).unwrap().0;
+            let bl = tiles.get(x - 1, y + 1).unwrap().0;
+            let l = tiles.get(x - 1, y).unwrap().0;
+            let tl = tiles.get(x - 1, y - 1).unwrap().0;
             let mut mx = 0u8;
             mx |= ((t < id) as u8) << 0;
             mx |= ((tr < id) as u8) << 1;
@@ -361,10 +415,9 @@ pub fn gen_light_buffers(
     tex: &mut Texture2D,
     x: usize, // units in tiles
     y: usize, // units in tiles
-    values: &Array2D<u8>,
-    _rgb: (u8, u8, u8),
+    (r, g, b): (&Array2D<u8>, &Array2D<u8>, &Array2D<u8>),
 ) {
-    let (w, h) = values.size();
+    let (w, h) = r.size();
 
     let x_px = (x * 16) as f32;
     let y_px = (y * 16) as f32;
@@ -390,20 +443,85 @@ pub fn gen_light_buffers(
     )
     .unwrap();
 
-    // Generate texture
+    // Generate texture, one texel per tile with each channel's brightness.
     let mut rgba: Vec<u8> = Vec::with_capacity(4 * w * h);
-    //let mut color = [rgb.0, rgb.1, rgb.2, 0];
-    values.for_each(|_, _, &v| {
-        let r = 0;
-        let g = 0;
-        let b = 0;
-        let a = 255 - 255 / MAX_BRIGHTNESS * v;
-        rgba.extend_from_slice(&[r, g, b, a]);
+    let scale = |v: u8| (v as u16 * 255 / MAX_BRIGHTNESS as u16) as u8;
+    r.for_each(|x, y, &v| {
+        let (vg, vb) = (g[x + y * w], b[x + y * w]);
+        rgba.extend_from_slice(&[scale(v), scale(vg), scale(vb), 255]);
     });
     tex.load_from_pixels(w as _, h as _, gl::RGBA, &rgba)
         .unwrap();
 }
 
+fn gen_item_buffers(
+    xy: &mut Buffer<(f32, f32)>,
+    uv: &mut Buffer<(f32, f32)>,
+    items: &Vec<(f32, f32, ItemId)>,
+) -> u32 {
+    let mut xy_vec = Vec::with_capacity(4 * items.len());
+    let mut uv_vec = Vec::with_capacity(4 * items.len());
+    for (x, y, item_id) in items {
+        xy_vec.extend_from_slice(&[
+            (*x, *y),
+            (*x + 16., *y),
+            (*x + 16., *y + 16.),
+            (*x, *y + 16.),
+        ]);
+        let (u, v) = item_id.properties().icon;
+        let (u, v) = (u as f32, v as f32);
+        uv_vec.extend_from_slice(&[(u, v), (u + 16., v), (u + 16., v + 16.), (u, v + 16.)]);
+    }
+    xy.init(gl::ARRAY_BUFFER, &xy_vec).unwrap();
+    uv.init(gl::ARRAY_BUFFER, &uv_vec).unwrap();
+    (xy_vec.len() / 4) as u32
+}
+
+/// Lays out the hotbar along the bottom of the view, with the rest of the inventory (if given)
+/// in rows above it: the icons, and text with slot numbers (the selected one in brackets) above
+/// counts. Each slot is 5 characters wide.
+fn layout_hotbar(
+    slots: &[Option<ItemStack>],
+    selected_slot: usize,
+    (view_x, view_y, view_w, view_h): (f32, f32, f32, f32),
+) -> (Vec<(f32, f32, ItemId)>, String, (f32, f32)) {
+    const SLOT_W: f32 = 5. * 8.;
+    const ROW_H: f32 = 3. * 14.;
+    let rows = icdiv(slots.len(), HOTBAR_SIZE);
+    let x = view_x + (view_w - SLOT_W * HOTBAR_SIZE as f32) / 2.;
+    let y = view_y + view_h - ROW_H * rows as f32 - 8.;
+
+    // Rows are laid out top down, so the hotbar (row 0) comes last.
+    let mut icons = vec![];
+    let mut text = String::new();
+    for row in (0..rows).rev() {
+        let row_y = y + ROW_H * (rows - 1 - row) as f32;
+        let mut numbers = String::new();
+        let mut counts = String::new();
+        for i in row * HOTBAR_SIZE..slots.len().min((row + 1) * HOTBAR_SIZE) {
+            let n = (i + 1) % 10;
+            match (row, i == selected_slot) {
+                (0, true) => numbers.push_str(&format!(" [{n}] ")),
+                (0, false) => numbers.push_str(&format!("  {n}  ")),
+                _ => {}
+            }
+            let column = (i % HOTBAR_SIZE) as f32;
+            match slots[i] {
+                Some(stack) => {
+                    icons.push((x + column * SLOT_W + 12., row_y + 14., stack.id));
+                    counts.push_str(&format!("{:>4} ", stack.count));
+                }
+                None => counts.push_str("     "),
+            }
+        }
+
+        // Icons take up the middle line.
+        text.push_str(&format!("{numbers}\n\n{counts}\n"));
+    }
+
+    (icons, text, (x, y))
+}
+
 fn gen_humanoid_buffers(
     xy: &mut Buffer<(f32, f32)>,
     rgb: &mut Buffer<(f32, f32, f32)>,
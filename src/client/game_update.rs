@@ -1,17 +1,35 @@
-use crate::array2d::{Array2D, FastArray2D};
-use std::collections::BTreeMap;
+use crate::array2d::{FastArray2D, Index2d};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 use super::game_frame::*;
 use super::input_event::*;
+use super::key_bindings::*;
+use super::snapshot::*;
 use crate::common::*;
 
-use crate::game::collision::*;
+use crate::game::delta::*;
 use crate::game::humanoid::*;
+use crate::game::inventory::*;
 use crate::game::item::*;
 use crate::game::lighting::*;
 use crate::game::net::*;
+use crate::game::sky::*;
 use crate::game::tile::*;
 
+// How many of the latest inputs go out with every frame, to cover packet loss.
+const INPUT_REDUNDANCY: usize = 8;
+
+// Decoded snapshots kept as delta baselines. Must cover the server's history.
+const SNAPSHOT_HISTORY: usize = 32;
+
+// Prediction.
+const PREDICTION_HISTORY: usize = 120; // Frames of unacknowledged input kept for replay.
+const SMOOTHING_DECAY: f32 = 0.85; // Fraction of a correction still shown after each frame.
+const MAX_SMOOTHING_DISTANCE: f32 = 64.; // Corrections larger than this (in pixels) snap.
+
+// How long to wait before asking to mine a tile again, after the server dropped it.
+const MINING_RETRY_US: u64 = 500_000;
+
 pub struct GameUpdate {
     // Misc:
     timer: usize,
@@ -20,16 +38,37 @@ pub struct GameUpdate {
     // Network:
     outbound: Vec<NetEvent>,
     chunks: FastArray2D<(u16, u16)>,
+    view_size_sent: bool,
 
     // Input:
     cursor_x: usize,
     cursor_y: usize,
-    cursor_left_queue: u8,
-    cursor_right_queue: u8,
-    up_queue: u8,
-    down_queue: u8,
-    left_queue: u8,
-    right_queue: u8,
+    key_map: HashMap<InputKey, Action>,
+    held_keys: HashSet<InputKey>,
+    action_queues: BTreeMap<Action, u8>, // Whether each action was held, over the last 8 frames.
+    input_frame: u32,
+    recent_inputs: VecDeque<HumanoidInput>,
+
+    // Snapshots:
+    entity_states: VecDeque<(u32, EntityState)>, // Decoded snapshots, by id.
+    latest_snapshot: Option<u32>,
+
+    // Prediction:
+    predictions: VecDeque<(HumanoidInput, HumanoidPhysics)>, // Inputs and the state they led to.
+    last_input_ack: Option<u32>,
+    smoothing: (f32, f32), // Visual offset of the player, decaying after a correction.
+    corrections: u64,
+
+    // Inventory:
+    inventory: Inventory,
+    selected_slot: usize,
+    inventory_open: bool,   // Whether every slot is shown, or just the hotbar.
+    mining: Option<Mining>, // Progress is a guess, the server decides when the tile breaks.
+    mining_dropped: Option<((u16, u16, bool), u64)>, // Target the server dropped, retry time.
+    show_debug: bool,
+
+    // World clock, kept running between the server's updates:
+    time_of_day_us: u64,
 
     // Client view:
     view_pos: (usize, usize),
@@ -37,10 +76,12 @@ pub struct GameUpdate {
 
     // Items:
     items: BTreeMap<u64, Item>,
+    item_snapshots: SnapshotBuffer<Item>,
 
     // Humanoids:
     player_id: u64,
     humanoids: BTreeMap<u64, Humanoid>,
+    humanoid_snapshots: SnapshotBuffer<HumanoidPhysics>,
 
     // Tiles:
     world_w: usize,
@@ -49,63 +90,27 @@ pub struct GameUpdate {
     background_tiles: FastArray2D<Tile>,
 
     // Lighting:
-    light_map_r: Array2D<u8>,
-    light_map_g: Array2D<u8>,
-    light_map_b: Array2D<u8>,
-    fade_map: Array2D<u8>,
+    light: LightEngine,
 }
 
 impl GameUpdate {
-    pub fn new(view_w: f32, view_h: f32, world_w: u16, world_h: u16, player_id: u64) -> Self {
+    pub fn new(
+        view_w: f32,
+        view_h: f32,
+        world_w: u16,
+        world_h: u16,
+        player_id: u64,
+        interpolation_delay_us: u64,
+        key_map: HashMap<InputKey, Action>,
+    ) -> Self {
         let view_w = view_w as usize;
         let view_h = view_h as usize;
         let world_w = world_w as usize;
         let world_h = world_h as usize;
 
-        //
-        let chunk_load_buffer_size_px = CHUNK_LOAD_BUFFER_SIZE * TILE_SIZE;
-        let chunk_size_px = TILE_SIZE * CHUNK_SIZE;
-
-        // Get number of chunks that will fit on screen.
-        let chunks_v = icdiv(view_w + 2 * chunk_load_buffer_size_px, chunk_size_px);
-        let chunks_h = icdiv(view_h + 2 * chunk_load_buffer_size_px, chunk_size_px);
-
-        // Get smallest base2 that can fit chunks_v/chunks_h.
-        let max_visible_chunks_v_base2 = (chunks_v as f32).log2().ceil() as usize;
-        let max_visible_chunks_h_base2 = (chunks_h as f32).log2().ceil() as usize;
-
-        // Create chunk array.
-        let chunks = FastArray2D::from_closure(
-            max_visible_chunks_v_base2,
-            max_visible_chunks_h_base2,
-            |_, _| (u16::max_value(), u16::max_value()),
-        );
-
-        // Create tile array (8 x 8) times larger than above array.
-        let foreground_tiles = FastArray2D::from_closure(
-            max_visible_chunks_v_base2 + CHUNK_SIZE_LOG2,
-            max_visible_chunks_h_base2 + CHUNK_SIZE_LOG2,
-            |_, _| Tile::None,
-        );
-
-        let background_tiles = FastArray2D::from_closure(
-            max_visible_chunks_v_base2 + CHUNK_SIZE_LOG2,
-            max_visible_chunks_h_base2 + CHUNK_SIZE_LOG2,
-            |_, _| Tile::None,
-        );
-
-        // Light
-        // most tiles that can be seen at once
-        let max_vis_w = icdiv(view_w - 1, TILE_SIZE) + 1;
-        let max_vis_h = icdiv(view_h - 1, TILE_SIZE) + 1;
-        let light_map_w = max_vis_w + 2 * MAX_LIGHT_DISTANCE;
-        let light_map_h = max_vis_h + 2 * MAX_LIGHT_DISTANCE;
-
-        // Init light map
-        let light_map_r = Array2D::from_closure(light_map_w, light_map_h, |_, _| MAX_BRIGHTNESS);
-        let light_map_g = Array2D::from_closure(light_map_w, light_map_h, |_, _| MAX_BRIGHTNESS);
-        let light_map_b = Array2D::from_closure(light_map_w, light_map_h, |_, _| MAX_BRIGHTNESS);
-        let fade_map = Array2D::from_closure(light_map_w, light_map_h, |_, _| MAX_FADE);
+        // Tiles (and their light) around the view, in a wrapping cache.
+        let (chunks, foreground_tiles, background_tiles, light) =
+            new_tile_cache(tile_cache_size(view_w, view_h));
 
         Self {
             timer: 0,
@@ -113,33 +118,49 @@ impl GameUpdate {
 
             cursor_x: 0,
             cursor_y: 0,
-            cursor_left_queue: 0,
-            cursor_right_queue: 0,
-            up_queue: 0,
-            down_queue: 0,
-            left_queue: 0,
-            right_queue: 0,
+            key_map,
+            held_keys: HashSet::new(),
+            action_queues: BTreeMap::new(),
+            input_frame: 0,
+            recent_inputs: VecDeque::new(),
+
+            entity_states: VecDeque::new(),
+            latest_snapshot: None,
+
+            predictions: VecDeque::new(),
+            last_input_ack: None,
+            smoothing: (0., 0.),
+            corrections: 0,
+
+            inventory: Inventory::new(),
+            selected_slot: 0,
+            inventory_open: false,
+            mining: None,
+            mining_dropped: None,
+            show_debug: true,
+
+            time_of_day_us: NEW_WORLD_TIME_US,
 
             view_pos: (0, 0),
             view_size: (view_w, view_h),
 
             outbound: Vec::new(),
             chunks,
+            view_size_sent: false,
 
             items: BTreeMap::new(),
+            item_snapshots: SnapshotBuffer::new(interpolation_delay_us),
 
             player_id,
             humanoids: BTreeMap::new(),
+            humanoid_snapshots: SnapshotBuffer::new(interpolation_delay_us),
 
             world_w,
             world_h,
             foreground_tiles,
             background_tiles,
 
-            light_map_r,
-            light_map_g,
-            light_map_b,
-            fade_map,
+            light,
         }
     }
 
@@ -161,23 +182,38 @@ impl GameUpdate {
                 NetEvent::Ping => {
                     self.outbound.push(NetEvent::Ping);
                 }
-                NetEvent::UpdateForegroundChunk(x, y, tiles) => {
-                    // Verify the incoming chunk exists in the world still, update tiles.
-                    if &(x, y) == self.chunks.get_wrapping(x as usize, y as usize) {
-                        self.foreground_tiles.splice_wrapping(
-                            CHUNK_SIZE * x as usize..CHUNK_SIZE * (x as usize + 1),
-                            CHUNK_SIZE * y as usize..CHUNK_SIZE * (y as usize + 1),
-                            tiles.clone(),
-                        );
+                NetEvent::Disconnect => {
+                    println!("[Client] Lost connection to the server.");
+                    self.exit = true;
+                }
+                NetEvent::UpdateInventory(inventory) => self.inventory = inventory,
+                NetEvent::StopMining => {
+                    if let Some(m) = self.mining.take() {
+                        let retry_us = timestamp_us + MINING_RETRY_US;
+                        self.mining_dropped = Some(((m.x, m.y, m.foreground), retry_us));
                     }
                 }
-                NetEvent::UpdateBackgroundChunk(x, y, tiles) => {
-                    // Verify the incoming chunk exists in the world still, update tiles.
-                    if &(x, y) == self.chunks.get_wrapping(x as usize, y as usize) {
-                        self.background_tiles.splice_wrapping(
-                            CHUNK_SIZE * x as usize..CHUNK_SIZE * (x as usize + 1),
-                            CHUNK_SIZE * y as usize..CHUNK_SIZE * (y as usize + 1),
-                            tiles.clone(),
+                NetEvent::WorldTime(time_of_day_us) => self.time_of_day_us = time_of_day_us,
+                NetEvent::UpdateChunks(chunks) => {
+                    for (x, y, chunk) in chunks {
+                        // Verify the incoming chunk exists in the world still, update tiles.
+                        if &(x, y) != self.chunks.get_wrapping(x as usize, y as usize) {
+                            continue;
+                        }
+                        let Some((fg, bg)) = chunk.decompress() else {
+                            continue;
+                        };
+                        let xr = CHUNK_SIZE * x as usize..CHUNK_SIZE * (x as usize + 1);
+                        let yr = CHUNK_SIZE * y as usize..CHUNK_SIZE * (y as usize + 1);
+                        self.foreground_tiles
+                            .splice_wrapping(xr.clone(), yr.clone(), fg);
+                        self.background_tiles
+                            .splice_wrapping(xr.clone(), yr.clone(), bg);
+                        self.light.update_tiles(
+                            &self.foreground_tiles,
+                            &self.background_tiles,
+                            xr,
+                            yr,
                         );
                     }
                 }
@@ -188,6 +224,7 @@ impl GameUpdate {
                         == self.chunks.get_wrapping(chunk_x, chunk_y);
                     if verify {
                         *self.foreground_tiles.get_wrapping_mut(x, y) = tile;
+                        self.update_light(x, y);
                     }
                 }
                 NetEvent::UpdateBackgroundTile(x, y, tile) => {
@@ -197,100 +234,198 @@ impl GameUpdate {
                         == self.chunks.get_wrapping(chunk_x, chunk_y);
                     if verify {
                         *self.background_tiles.get_wrapping_mut(x, y) = tile;
+                        self.update_light(x, y);
                     }
                 }
-                NetEvent::ItemData(items) => {
-                    self.items = items;
-                }
-                NetEvent::HumanoidData(humanoids) => {
-                    let player = self.humanoids.get(&self.player_id).cloned();
-                    self.humanoids = BTreeMap::from_iter(
-                        humanoids
-                            .into_iter()
-                            .map(|(key, value)| (key, Humanoid { physics: value })),
-                    );
-                    if let (Some(old_player), Some(new_player)) =
-                        (player, self.humanoids.get_mut(&self.player_id))
-                    {
-                        *new_player = old_player;
+                NetEvent::Snapshot(delta) => {
+                    // Rebuild the full snapshot. Skip it if its baseline is gone, a later one
+                    // will be based on a snapshot we've acked.
+                    let empty = EntityState::default();
+                    let baseline = match delta.baseline {
+                        Some(baseline) => self
+                            .entity_states
+                            .iter()
+                            .find(|&&(id, _)| id == baseline)
+                            .map(|(_, state)| state),
+                        None => Some(&empty),
+                    };
+                    let Some(state) = baseline.map(|baseline| baseline.apply(&delta)) else {
+                        continue;
+                    };
+
+                    // Everyone else is drawn from the buffered snapshots (see step).
+                    let (server_us, ack) = (delta.timestamp_us, delta.input_ack);
+                    let humanoids = state.humanoids.iter().map(|(&id, h)| (id, h.into()));
+                    let items = state.items.iter().map(|(&id, i)| (id, i.into()));
+                    self.humanoid_snapshots
+                        .push(timestamp_us, server_us, humanoids.collect());
+                    self.item_snapshots
+                        .push(timestamp_us, server_us, items.collect());
+
+                    // Keep it as a baseline.
+                    if !self.entity_states.iter().any(|&(id, _)| id == delta.id) {
+                        self.entity_states.push_back((delta.id, state));
+                        if self.entity_states.len() > SNAPSHOT_HISTORY {
+                            self.entity_states.pop_front();
+                        }
                     }
+                    self.latest_snapshot = self.latest_snapshot.max(Some(delta.id));
+
+                    // Skip snapshots that arrive out of order.
+                    if let (Some(ack), Some(last_ack)) = (ack, self.last_input_ack) {
+                        if ack < last_ack {
+                            continue;
+                        }
+                    }
+                    self.last_input_ack = ack.or(self.last_input_ack);
+
+                    // Check the server's copy of the player against what was predicted.
+                    let Some(server_player) = delta.player else {
+                        continue;
+                    };
+                    let physics = match self.humanoids.get(&self.player_id) {
+                        Some(player) => self.reconcile(ack, server_player, player.physics),
+                        None => server_player,
+                    };
+                    self.humanoids.insert(self.player_id, Humanoid { physics });
                 }
                 _ => {}
             }
         }
 
         // Shift left, cloning right most bit.
-        queue_shift_u8(&mut self.cursor_left_queue);
-        queue_shift_u8(&mut self.cursor_right_queue);
-        queue_shift_u8(&mut self.up_queue);
-        queue_shift_u8(&mut self.down_queue);
-        queue_shift_u8(&mut self.left_queue);
-        queue_shift_u8(&mut self.right_queue);
+        self.action_queues.values_mut().for_each(queue_shift_u8);
 
         // Input loop.
         for input in input_events {
             match input {
                 InputEvent::Close => {
-                    self.exit = true; 
-                }
-                InputEvent::KeyEvent(KeyState::Down, InputKey::W) => {
-                    queue_set_u8(&mut self.up_queue)
-                }
-                InputEvent::KeyEvent(KeyState::Down, InputKey::A) => {
-                    queue_set_u8(&mut self.left_queue)
+                    self.exit = true;
                 }
-                InputEvent::KeyEvent(KeyState::Down, InputKey::S) => {
-                    queue_set_u8(&mut self.down_queue)
-                }
-                InputEvent::KeyEvent(KeyState::Down, InputKey::D) => {
-                    queue_set_u8(&mut self.right_queue)
-                }
-                InputEvent::KeyEvent(KeyState::Up, InputKey::W) => {
-                    queue_clear_u8(&mut self.up_queue)
-                }
-                InputEvent::KeyEvent(KeyState::Up, InputKey::A) => {
-                    queue_clear_u8(&mut self.left_queue)
-                }
-                InputEvent::KeyEvent(KeyState::Up, InputKey::S) => {
-                    queue_clear_u8(&mut self.down_queue)
+                InputEvent::KeyEvent(state, key) => {
+                    let Some(&action) = self.key_map.get(&key) else {
+                        continue;
+                    };
+                    match state {
+                        KeyState::Down => self.held_keys.insert(key),
+                        KeyState::Up => self.held_keys.remove(&key),
+                    };
+
+                    // Actions are held for as long as any of their keys are.
+                    let held = self.held_keys.iter().any(|k| self.key_map[k] == action);
+                    let queue = self.action_queues.entry(action).or_insert(0);
+                    match held {
+                        true => queue_set_u8(queue),
+                        false => queue_clear_u8(queue),
+                    }
                 }
-                InputEvent::KeyEvent(KeyState::Up, InputKey::D) => {
-                    queue_clear_u8(&mut self.right_queue)
+                InputEvent::Scroll(lines) => {
+                    let steps = -lines.round() as isize;
+                    let slot = (self.selected_slot as isize + steps).rem_euclid(HOTBAR_SIZE as _);
+                    self.select_slot(slot as usize);
                 }
 
                 InputEvent::CursorMove(x, y) => (self.cursor_x, self.cursor_y) = (x as _, y as _),
-                InputEvent::KeyEvent(KeyState::Down, InputKey::LeftClick) => {
-                    queue_set_u8(&mut self.cursor_left_queue);
-                }
-                InputEvent::KeyEvent(KeyState::Down, InputKey::RightClick) => {
-                    queue_set_u8(&mut self.cursor_right_queue);
-                }
-                InputEvent::KeyEvent(KeyState::Up, InputKey::LeftClick) => {
-                    queue_clear_u8(&mut self.cursor_left_queue);
-                }
-                InputEvent::KeyEvent(KeyState::Up, InputKey::RightClick) => {
-                    queue_clear_u8(&mut self.cursor_right_queue)
+                InputEvent::WindowResize(w, h) if w > 0 && h > 0 => {
+                    let (w, h) = (w as usize, h as usize);
+                    self.view_size = (w, h);
+                    self.view_size_sent = false;
+
+                    // The tile cache only fits the view it was made for, start over if it changed.
+                    let (w_exp, h_exp) = tile_cache_size(w, h);
+                    if self.chunks.size() != (1 << w_exp, 1 << h_exp) {
+                        (
+                            self.chunks,
+                            self.foreground_tiles,
+                            self.background_tiles,
+                            self.light,
+                        ) = new_tile_cache((w_exp, h_exp));
+                    }
                 }
                 _ => continue,
             };
         }
 
-        // On left click
-        if self.cursor_left_queue & 0b1 == 1 && self.cursor_left_queue & 0b10 == 0 {
-            let x = (self.view_pos.0 + self.cursor_x) / 16;
-            let y = (self.view_pos.1 + self.cursor_y) / 16;
-            *self.foreground_tiles.get_wrapping_mut(x, y) = Tile::None;
-            self.outbound
-                .push(NetEvent::BreakForeground(x as _, y as _));
+        // Hotbar selection.
+        for slot in 0..HOTBAR_SIZE {
+            if self.pressed(Action::Hotbar(slot as u8)) {
+                self.select_slot(slot);
+            }
+        }
+        if self.pressed(Action::HotbarNext) {
+            self.select_slot((self.selected_slot + 1) % HOTBAR_SIZE);
+        }
+        if self.pressed(Action::HotbarPrev) {
+            self.select_slot((self.selected_slot + HOTBAR_SIZE - 1) % HOTBAR_SIZE);
         }
 
-        // On right click
-        if self.cursor_right_queue & 0b1 == 1 && self.cursor_right_queue & 0b10 == 0 {
-            let x = (self.view_pos.0 + self.cursor_x) / 16;
-            let y = (self.view_pos.1 + self.cursor_y) / 16;
-            *self.background_tiles.get_wrapping_mut(x, y) = Tile::None;
-            self.outbound
-                .push(NetEvent::BreakBackground(x as _, y as _));
+        // Toggles. There's no chat yet, so Action::Chat does nothing for now.
+        if self.pressed(Action::Inventory) {
+            self.inventory_open = !self.inventory_open;
+        }
+        if self.pressed(Action::ToggleDebug) {
+            self.show_debug = !self.show_debug;
+        }
+
+        // Only tiles within reach can be edited.
+        let x = (self.view_pos.0 + self.cursor_x) / 16;
+        let y = (self.view_pos.1 + self.cursor_y) / 16;
+        let player = self.humanoids.get(&self.player_id);
+        let reachable = player.map_or(false, |h| can_reach(&h.physics, x, y, HUMANOID_REACH));
+        let blocked = player.map_or(false, |h| overlaps_tile(&h.physics, x, y));
+
+        // Clicking empty space places the selected tile, holding on a tile mines it. The left
+        // button works on the foreground, the right one on the background.
+        let selected = self.inventory.get(self.selected_slot);
+        let placed = selected.and_then(|s| s.id.placed_tile());
+        let mut target = None;
+        for (action, foreground) in [(Action::Secondary, false), (Action::Primary, true)] {
+            let (pressed, held) = (self.pressed(action), self.held(action));
+            let tile = match foreground {
+                true => self.foreground_tiles.get_wrapping_mut(x, y),
+                false => self.background_tiles.get_wrapping_mut(x, y),
+            };
+            match placed {
+                _ if !reachable => {}
+                Some(placed)
+                    if *tile == Tile::NONE
+                        && pressed
+                        && !(foreground && blocked && placed.is_solid()) =>
+                {
+                    *tile = placed;
+                    self.update_light(x, y);
+                    self.outbound.push(match foreground {
+                        true => NetEvent::PlaceForeground(x as _, y as _),
+                        false => NetEvent::PlaceBackground(x as _, y as _),
+                    });
+                }
+                _ if *tile != Tile::NONE && held => {
+                    target = Some((x as u16, y as u16, foreground));
+                }
+                _ => {}
+            }
+        }
+
+        // Give the server a moment before asking again for a target it dropped.
+        if let Some((dropped, retry_us)) = self.mining_dropped {
+            if target == Some(dropped) && timestamp_us < retry_us {
+                target = None;
+            }
+        }
+
+        // Tell the server when the target changes. The tile only breaks once it says so.
+        if target != self.mining.map(|m| (m.x, m.y, m.foreground)) {
+            self.outbound.push(match target {
+                Some((x, y, true)) => NetEvent::BreakForeground(x, y),
+                Some((x, y, false)) => NetEvent::BreakBackground(x, y),
+                None => NetEvent::StopMining,
+            });
+            self.mining = target.map(|(x, y, foreground)| Mining {
+                x,
+                y,
+                foreground,
+                progress_us: 0,
+            });
         }
     }
 
@@ -300,10 +435,15 @@ impl GameUpdate {
         let _timestamp_ms = timestamp_us / 1_000;
         let _timestamp_s = timestamp_us / 1_000_000;
 
+        // Mining.
+        if let Some(mining) = &mut self.mining {
+            mining.progress_us += frametime;
+        }
+
         // Center camera around humanoids (if it exists).
-        if let Some(player) = self.humanoids.get(&self.player_id) {
-            self.view_pos.0 = (player.physics.x as usize).saturating_sub(self.view_size.0 / 2);
-            self.view_pos.1 = (player.physics.y as usize).saturating_sub(self.view_size.1 / 2);
+        if let Some((x, y)) = self.player_position() {
+            self.view_pos.0 = (x as usize).saturating_sub(self.view_size.0 / 2);
+            self.view_pos.1 = (y as usize).saturating_sub(self.view_size.1 / 2);
         };
 
         // Currect the view if it is out of bounds.
@@ -316,127 +456,83 @@ impl GameUpdate {
             .saturating_sub(self.view_size.1)
             .max(16);
 
-        // Update item state.
-        let mut tmp = vec![];
-        for item in self.items.values_mut() {
-            let old_y = item.y;
-            update_item_physics_y(dt, item, ITEM_GRAVITY);
-            tmp.clear();
-            let ty = collect_newly_colliding_tiles_y(
-                old_y,
-                item.x,
-                item.y,
-                16.,
-                16.,
-                &self.foreground_tiles,
-                &mut tmp,
-            );
-            resolve_item_tile_collision_y(item, ty, &tmp);
+        // Draw items and other humanoids between the server's snapshots.
+        if let Some(items) = self.item_snapshots.sample(timestamp_us) {
+            self.items = items;
         }
-
-        // Update player state.
-        for (id, humanoid) in &mut self.humanoids {
-            // Player physics [TODO: make this neater]
-            let physics = &mut humanoid.physics;
-
-            // Calculate acceleration forces
-            let (ddx, ddy) = {
-                if *id == self.player_id {
-                    let left_cmd = self.left_queue & 0b1 == 0b1;
-                    let right_cmd = self.right_queue & 0b1 == 0b1;
-                    let jump_cmd = self.up_queue & 0b11 == 0b01;
-                    let grounded = physics.grounded;
-
-                    // Cancel all acceleration.
-                    let mut ddx = 0.;
-                    let mut ddy = HUMANOID_GRAVITY;
-
-                    // Move player right
-                    if right_cmd && !left_cmd && physics.dx < 9. * 16. {
-                        ddx += 16. * 16.;
-                    }
-                    // Move player left
-                    if left_cmd && !right_cmd && physics.dx > -9. * 16. {
-                        ddx += -16. * 16.;
-                    }
-                    // Else friction?
-                    if !(right_cmd && physics.dx > 0.0 || left_cmd && physics.dx < 0.0) {
-                        ddx += -physics.dx * 4.;
-                    }
-
-                    // Jump
-                    if jump_cmd && grounded {
-                        ddy += -12000.;
-                    }
-                    (ddx, ddy)
-                } else {
-                    (0., HUMANOID_GRAVITY)
-                }
-            };
-
-            // Upldate player physics (y).
-            let last_y = physics.y;
-            update_humanoid_physics_y(dt, physics, ddy);
-
-            // Calculate tiles that are now colliding with the player.
-            tmp.clear();
-            let ty = collect_newly_colliding_tiles_y(
-                last_y,
-                physics.x,
-                physics.y,
-                HUMANOID_WIDTH as f32,
-                HUMANOID_HEIGHT as f32,
-                &self.foreground_tiles,
-                &mut tmp,
+        if let Some(humanoids) = self.humanoid_snapshots.sample(timestamp_us) {
+            let player = self.humanoids.remove(&self.player_id);
+            self.humanoids = BTreeMap::from_iter(
+                humanoids
+                    .into_iter()
+                    .filter(|&(id, _)| id != self.player_id)
+                    .map(|(id, physics)| (id, Humanoid { physics })),
             );
+            if let Some(player) = player {
+                self.humanoids.insert(self.player_id, player);
+            }
+        }
 
-            // Resolve colliding tiles.
-            resolve_humanoid_tile_collision_y(physics, ty, &tmp);
-
-            // Upldate player physics (x).
-            let last_x = physics.x;
-            update_humanoid_physics_x(dt, physics, ddx);
-
-            // Calculate tiles that are now colliding with the player.
-            tmp.clear();
-            let tx = collect_newly_colliding_tiles_x(
-                last_x,
-                physics.x,
-                physics.y,
-                HUMANOID_WIDTH as f32,
-                HUMANOID_HEIGHT as f32,
+        // Sample input for this frame.
+        let input = HumanoidInput {
+            frame: self.input_frame,
+            left: self.held(Action::MoveLeft),
+            right: self.held(Action::MoveRight),
+            jump: self.pressed(Action::Jump),
+        };
+        self.input_frame += 1;
+        if self.recent_inputs.len() == INPUT_REDUNDANCY {
+            self.recent_inputs.pop_front();
+        }
+        self.recent_inputs.push_back(input);
+
+        // Predict the player.
+        if let Some(player) = self.humanoids.get_mut(&self.player_id) {
+            let mut tmp = vec![];
+            step_humanoid(
+                dt,
+                &mut player.physics,
+                &input,
                 &self.foreground_tiles,
                 &mut tmp,
             );
-
-            // Resolve colliding tiles.
-            resolve_humanoid_tile_collision_x(physics, tx, &tmp);
         }
 
-        // Clear light map.
-        let (w, h) = self.light_map_r.size();
-        self.light_map_r
-            .for_each_sub_wrapping_mut(1..w - 1, 1..h - 1, |_, _, t| *t = MIN_BRIGHTNESS);
-        self.light_map_g
-            .for_each_sub_wrapping_mut(1..w - 1, 1..h - 1, |_, _, t| *t = MIN_BRIGHTNESS);
-        self.light_map_b
-            .for_each_sub_wrapping_mut(1..w - 1, 1..h - 1, |_, _, t| *t = MIN_BRIGHTNESS);
-
-        // Generate a fade map.
-        let lights = gen_fade_map(
-            self.view_pos,
-            &self.foreground_tiles,
-            &self.background_tiles,
-            &mut self.fade_map,
-            &mut self.light_map_r,
-            &mut self.light_map_g,
-            &mut self.light_map_b,
-        );
+        // Remember the prediction, so it can be checked and replayed.
+        if let Some(player) = self.humanoids.get(&self.player_id) {
+            if self.predictions.len() == PREDICTION_HISTORY {
+                self.predictions.pop_front();
+            }
+            self.predictions.push_back((input, player.physics));
+        }
+        self.smoothing.0 *= SMOOTHING_DECAY;
+        self.smoothing.1 *= SMOOTHING_DECAY;
+
+        // Sky light follows the time of day.
+        self.time_of_day_us = (self.time_of_day_us + frametime) % DAY_LENGTH_US;
+        self.light.set_sky(sky_light(self.time_of_day_us));
+
+        // Glowing items, on the ground or in the player's hand.
+        let mut emitters = Vec::new();
+        for item in self.items.values() {
+            let light = item.id.properties().light;
+            if light != (0, 0, 0) {
+                let x = (item.x + 0.5 * TILE_SIZE as f32) as usize / TILE_SIZE;
+                let y = (item.y + 0.5 * TILE_SIZE as f32) as usize / TILE_SIZE;
+                emitters.push((x, y, light));
+            }
+        }
+        let held = self.inventory.get(self.selected_slot);
+        if let (Some(stack), Some((x, y))) = (held, self.player_position()) {
+            let light = stack.id.properties().light;
+            if light != (0, 0, 0) {
+                let x = (x + 0.5 * HUMANOID_WIDTH as f32) as usize / TILE_SIZE;
+                let y = (y + 0.5 * HUMANOID_HEIGHT as f32) as usize / TILE_SIZE;
+                emitters.push((x, y, light));
+            }
+        }
 
-        // Generate final light map.
-        propogate_light_map_unbounded(&mut self.light_map_r, &self.fade_map, lights.clone());
-        //propogate_light_map_unbounded(&mut self.light_map_g, &self.fade_map, lights.clone());
-        //propogate_light_map_unbounded(&mut self.light_map_b, &self.fade_map, lights.clone());
+        self.light.set_emitters(emitters);
 
         // Request from the server any chunks that may now be onscreen (Should client be the one to ask this?).
         request_chunks_from_server(
@@ -445,15 +541,26 @@ impl GameUpdate {
             &mut self.chunks,
             &mut self.outbound,
         );
-
-        // Send server player data
-        if let Some(player) = self.humanoids.get(&self.player_id) {
-            self.outbound
-                .push(NetEvent::UpdateHumanoid(self.player_id, player.physics));
-        }
     }
 
     pub fn postframe(&mut self, _timestamp: u64) -> (Option<GameFrame>, &[NetEvent]) {
+        // Tell the server how much we can see (again after a resize), so it sends the entities
+        // around it.
+        if !self.view_size_sent {
+            let (w, h) = self.view_size;
+            self.outbound.push(NetEvent::ViewSize(w as u16, h as u16));
+            self.view_size_sent = true;
+        }
+
+        // Send the server our latest inputs.
+        let inputs = Vec::from_iter(self.recent_inputs.iter().copied());
+        self.outbound.push(NetEvent::PlayerInput(inputs));
+
+        // Let the server know which snapshot to send deltas against.
+        if let Some(id) = self.latest_snapshot {
+            self.outbound.push(NetEvent::AckSnapshot(id));
+        }
+
         // Clone the visible tiles
         const VISIBLE_TILE_BUFFER: usize = 2;
         let x1 = ifdiv(self.view_pos.0 - VISIBLE_TILE_BUFFER, TILE_SIZE).saturating_sub(1);
@@ -470,57 +577,68 @@ impl GameUpdate {
         let background_tiles = self.background_tiles.clone_sub_wrapping(x1..x2, y1..y2);
         let (tiles_x, tiles_y) = (x1, y1);
 
-        // Clone the innermost square of the light map
+        // Clone the onscreen part of the light map
         let camx1 = ifdiv(self.view_pos.0, TILE_SIZE);
         let camx2 = icdiv(self.view_pos.0 + self.view_size.0, TILE_SIZE);
         let camy1 = ifdiv(self.view_pos.1, TILE_SIZE);
         let camy2 = icdiv(self.view_pos.1 + self.view_size.1, TILE_SIZE);
-        let lmx = ifdiv(self.view_pos.0, TILE_SIZE).saturating_sub(MAX_LIGHT_DISTANCE);
-        let lmy = ifdiv(self.view_pos.1, TILE_SIZE).saturating_sub(MAX_LIGHT_DISTANCE);
-        let light_map_r = self
-            .light_map_r
-            .clone_sub(camx1 - lmx..camx2 - lmx, camy1 - lmy..camy2 - lmy)
-            .unwrap();
-        let light_map_g = self
-            .light_map_g
-            .clone_sub(camx1 - lmx..camx2 - lmx, camy1 - lmy..camy2 - lmy)
-            .unwrap();
-        let light_map_b = self
-            .light_map_b
-            .clone_sub(camx1 - lmx..camx2 - lmx, camy1 - lmy..camy2 - lmy)
-            .unwrap();
+        let [light_map_r, light_map_g, light_map_b] = &self.light.rgb;
+        let light_map_r = light_map_r.clone_sub_wrapping(camx1..camx2, camy1..camy2);
+        let light_map_g = light_map_g.clone_sub_wrapping(camx1..camx2, camy1..camy2);
+        let light_map_b = light_map_b.clone_sub_wrapping(camx1..camx2, camy1..camy2);
 
         // Prepare player data.
         let humanoid_positions: Vec<(f32, f32)> = self
             .humanoids
-            .values()
-            .map(|h| (h.physics.x, h.physics.y))
+            .iter()
+            .map(|(&id, h)| match id == self.player_id {
+                true => self.player_position().unwrap(),
+                false => (h.physics.x, h.physics.y),
+            })
             .collect();
 
+        // Prepare mining data, as a fraction of the time the tile takes.
+        let mining = self.mining.map(|m| {
+            let tile = match m.foreground {
+                true => *self.foreground_tiles.get_wrapping(m.x as _, m.y as _),
+                false => *self.background_tiles.get_wrapping(m.x as _, m.y as _),
+            };
+            let tool = self.inventory.get(self.selected_slot).map(|s| s.id);
+            let progress = m.progress_us as f32 / mining_time_us(tile, tool).max(1) as f32;
+            (m.x as usize, m.y as usize, progress.min(1.))
+        });
+
         // Prepare item data.
         let items: Vec<(f32, f32, ItemId)> =
             self.items.values().map(|i| (i.x, i.y, i.id)).collect();
 
         #[rustfmt::skip]
-        let debug_text = {
-            let left_queue = self.left_queue;
-            let right_queue = self.right_queue;
-            let up_queue = self.up_queue;
-            let (world_w, world_h) = (self.world_w, self.world_h);
-            let player_phys = self.humanoids.get(&self.player_id).map(|p| p.physics);
-            format!("\
-                player: {player_phys:.01?}\n\
-                world size: {world_w}x{world_h}\n\
-                left:  {left_queue:08b}\n\
-                right: {right_queue:08b}\n\
-                up:    {up_queue:08b}\n\
-            ")
+        let debug_text = match self.show_debug {
+            false => String::new(),
+            true => {
+                let held: Vec<_> = self.action_queues.keys().filter(|&&a| self.held(a)).collect();
+                let (world_w, world_h) = (self.world_w, self.world_h);
+                let player_phys = self.humanoids.get(&self.player_id).map(|p| p.physics);
+                let (unacked, corrections) = (self.predictions.len(), self.corrections);
+                let selected = self.inventory.get(self.selected_slot);
+                let time = format_time_of_day(self.time_of_day_us);
+                format!("\
+                    time: {time}\n\
+                    player: {player_phys:.01?}\n\
+                    unacked inputs: {unacked}, corrections: {corrections}\n\
+                    selected: {selected:?}\n\
+                    world size: {world_w}x{world_h}\n\
+                    held: {held:?}\n\
+                ")
+            }
         };
 
         // Construct frame.
         let frame = (!self.exit).then(|| GameFrame {
             debug_text,
 
+            sky_color: sky_color(self.time_of_day_us),
+
             view_x: self.view_pos.0,
             view_y: self.view_pos.1,
             view_w: self.view_size.0,
@@ -528,12 +646,19 @@ impl GameUpdate {
 
             items,
 
+            hotbar: match self.inventory_open {
+                true => self.inventory.slots.clone(),
+                false => self.inventory.slots[..HOTBAR_SIZE].to_vec(),
+            },
+            selected_slot: self.selected_slot,
+
             humanoid_positions,
 
             tiles_x,
             tiles_y,
             foreground_tiles,
             background_tiles,
+            mining,
 
             light_x: camx1, // TEMP
             light_y: camy1, // TEMP
@@ -545,6 +670,158 @@ impl GameUpdate {
         // Return.
         (frame, &self.outbound)
     }
+
+    /// Returns true if ``action`` is held this frame.
+    fn held(&self, action: Action) -> bool {
+        self.action_queues
+            .get(&action)
+            .map_or(false, |q| q & 0b1 == 0b1)
+    }
+
+    /// Returns true if ``action`` started being held this frame.
+    fn pressed(&self, action: Action) -> bool {
+        self.action_queues
+            .get(&action)
+            .map_or(false, |q| q & 0b11 == 0b01)
+    }
+
+    /// Selects a hotbar slot, and tells the server.
+    fn select_slot(&mut self, slot: usize) {
+        if slot != self.selected_slot {
+            self.selected_slot = slot;
+            self.outbound.push(NetEvent::SelectSlot(slot as u8));
+        }
+    }
+
+    /// Turns the debug text on or off, like Action::ToggleDebug.
+    pub fn set_show_debug(&mut self, show_debug: bool) {
+        self.show_debug = show_debug;
+    }
+
+    /// How many times the server disagreed with where the player was predicted to be.
+    pub fn corrections(&self) -> u64 {
+        self.corrections
+    }
+
+    /// Inputs sent that the server hasn't applied yet.
+    pub fn unacked_inputs(&self) -> usize {
+        self.predictions.len()
+    }
+
+    /// Where the player is drawn, with any correction still being smoothed out.
+    fn player_position(&self) -> Option<(f32, f32)> {
+        let player = self.humanoids.get(&self.player_id)?;
+        let (x, y) = (player.physics.x, player.physics.y);
+        Some((x + self.smoothing.0, y + self.smoothing.1))
+    }
+
+    // Relights around the tile at (x, y) after it changed.
+    fn update_light(&mut self, x: usize, y: usize) {
+        self.light.update_tiles(
+            &self.foreground_tiles,
+            &self.background_tiles,
+            x..x + 1,
+            y..y + 1,
+        );
+    }
+
+    /// Takes the server's state of the player, as of input frame ``ack``. If it differs from what
+    /// was predicted for that frame, rewinds to it and replays the inputs the server hasn't
+    /// applied yet. Returns the player's corrected current state.
+    fn reconcile(
+        &mut self,
+        ack: Option<u32>,
+        server: HumanoidPhysics,
+        current: HumanoidPhysics,
+    ) -> HumanoidPhysics {
+        // Forget inputs the server has applied, keeping the prediction for the last one.
+        let mut predicted = None;
+        while let Some(&(input, physics)) = self.predictions.front() {
+            if ack.map_or(true, |ack| input.frame > ack) {
+                break;
+            }
+            predicted = Some(physics);
+            self.predictions.pop_front();
+        }
+
+        // Nothing to do if the prediction was right.
+        if predicted.map_or(false, |p| physics_match(&p, &server)) {
+            return current;
+        }
+
+        // Rewind and replay.
+        let dt = INPUT_FRAMETIME_US as f32 / 1_000_000.;
+        let mut physics = server;
+        let mut tmp = vec![];
+        for (input, predicted) in &mut self.predictions {
+            step_humanoid(dt, &mut physics, input, &self.foreground_tiles, &mut tmp);
+            *predicted = physics;
+        }
+        self.corrections += 1;
+
+        // Hide the jump by drawing the player where it was, then easing it back.
+        let offset = (
+            self.smoothing.0 + current.x - physics.x,
+            self.smoothing.1 + current.y - physics.y,
+        );
+        self.smoothing = match offset.0.hypot(offset.1) < MAX_SMOOTHING_DISTANCE {
+            true => offset,
+            false => (0., 0.),
+        };
+
+        physics
+    }
+}
+
+/// Returns true if two humanoid states are close enough to count as the same.
+fn physics_match(a: &HumanoidPhysics, b: &HumanoidPhysics) -> bool {
+    const EPSILON: f32 = 0.01;
+    (a.x - b.x).abs() < EPSILON
+        && (a.y - b.y).abs() < EPSILON
+        && (a.dx - b.dx).abs() < EPSILON
+        && (a.dy - b.dy).abs() < EPSILON
+        && a.grounded == b.grounded
+}
+
+/// Returns how many chunks (as powers of two) the tile cache needs, to cover a view of ``view_w``
+/// by ``view_h`` pixels plus the chunk load buffer.
+fn tile_cache_size(view_w: usize, view_h: usize) -> (usize, usize) {
+    let chunk_load_buffer_size_px = CHUNK_LOAD_BUFFER_SIZE * TILE_SIZE;
+    let chunk_size_px = TILE_SIZE * CHUNK_SIZE;
+
+    // Get number of chunks that will fit on screen.
+    let chunks_v = icdiv(view_w + 2 * chunk_load_buffer_size_px, chunk_size_px);
+    let chunks_h = icdiv(view_h + 2 * chunk_load_buffer_size_px, chunk_size_px);
+
+    // Get smallest base2 that can fit chunks_v/chunks_h.
+    let max_visible_chunks_v_base2 = (chunks_v as f32).log2().ceil() as usize;
+    let max_visible_chunks_h_base2 = (chunks_h as f32).log2().ceil() as usize;
+    (max_visible_chunks_v_base2, max_visible_chunks_h_base2)
+}
+
+/// Creates an empty tile cache ``w_exp`` by ``h_exp`` chunks (as powers of two) in size: which
+/// chunk each slot holds, the foreground and background tiles, and their light.
+fn new_tile_cache(
+    (w_exp, h_exp): (usize, usize),
+) -> (
+    FastArray2D<(u16, u16)>,
+    FastArray2D<Tile>,
+    FastArray2D<Tile>,
+    LightEngine,
+) {
+    // Create chunk array.
+    let chunks =
+        FastArray2D::from_closure(w_exp, h_exp, |_, _| (u16::max_value(), u16::max_value()));
+
+    // Create tile array (8 x 8) times larger than above array.
+    let (tiles_w_exp, tiles_h_exp) = (w_exp + CHUNK_SIZE_LOG2, h_exp + CHUNK_SIZE_LOG2);
+    let foreground_tiles = FastArray2D::from_closure(tiles_w_exp, tiles_h_exp, |_, _| Tile::NONE);
+    let background_tiles = FastArray2D::from_closure(tiles_w_exp, tiles_h_exp, |_, _| Tile::NONE);
+
+    // Light, laid out the same as the tiles.
+    let light = LightEngine::new(tiles_w_exp, tiles_h_exp);
+
+    (chunks, foreground_tiles, background_tiles, light)
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -1,29 +1,100 @@
 #![allow(dead_code)]
 
+use serde::Deserialize;
+
+#[derive(Copy, Clone, Debug)]
 pub enum InputEvent {
     CursorMove(f32, f32),
     WindowResize(u16, u16),
     Focused(bool),
     KeyEvent(KeyState, InputKey),
+    Scroll(f32), // In lines, positive is up.
     Close,
 }
 
+#[derive(Copy, Clone, Debug)]
 pub enum KeyState {
     Up,
     Down,
 }
 
+/// A keyboard key or mouse button. Keys glutin knows but that aren't listed here are ignored.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum InputKey {
-    W,
     A,
-    S,
+    B,
+    C,
     D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
     Z,
+    Key0,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
     Left,
     Right,
     Up,
     Down,
     Space,
+    Enter,
+    Escape,
+    Tab,
+    Backspace,
+    LShift,
+    RShift,
+    LControl,
+    RControl,
+    LAlt,
+    RAlt,
+    Grave,
+    Minus,
+    Equals,
+    LBracket,
+    RBracket,
+    Backslash,
+    Semicolon,
+    Apostrophe,
+    Comma,
+    Period,
+    Slash,
     LeftClick,
     RightClick,
     MiddleClick,
@@ -0,0 +1,172 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::client::input_event::*;
+use crate::game::inventory::*;
+
+/// Something the player does by pressing or holding a key (or mouse button).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Primary,    // Break or place in the foreground.
+    Secondary,  // Break or place in the background.
+    Hotbar(u8), // Select a hotbar slot.
+    HotbarNext,
+    HotbarPrev,
+    Inventory,
+    Chat,
+    ToggleDebug,
+}
+
+/// Which keys trigger each action. Loaded from the ``[bindings]`` table of the client config, keyed
+/// by the same names as --bind (like "jump" or "hotbar_3"), where any action left out keeps its
+/// default keys. A key can only be bound to one action.
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    pub move_left: Vec<InputKey>,
+    pub move_right: Vec<InputKey>,
+    pub jump: Vec<InputKey>,
+    pub primary: Vec<InputKey>,
+    pub secondary: Vec<InputKey>,
+    pub hotbar: [Vec<InputKey>; HOTBAR_SIZE],
+    pub hotbar_next: Vec<InputKey>,
+    pub hotbar_prev: Vec<InputKey>,
+    pub inventory: Vec<InputKey>,
+    pub chat: Vec<InputKey>,
+    pub toggle_debug: Vec<InputKey>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use InputKey::*;
+        Self {
+            move_left: vec![A, Left],
+            move_right: vec![D, Right],
+            jump: vec![W, Space, Up],
+            primary: vec![LeftClick],
+            secondary: vec![RightClick],
+            hotbar: [
+                vec![Key1],
+                vec![Key2],
+                vec![Key3],
+                vec![Key4],
+                vec![Key5],
+                vec![Key6],
+                vec![Key7],
+                vec![Key8],
+                vec![Key9],
+                vec![Key0],
+            ],
+            hotbar_next: vec![],
+            hotbar_prev: vec![],
+            inventory: vec![E],
+            chat: vec![Enter],
+            toggle_debug: vec![F3],
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBindings {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = HashMap::<String, Vec<InputKey>>::deserialize(deserializer)?;
+        let mut bindings = Self::default();
+        for (name, keys) in table {
+            bindings
+                .rebind(&name, keys)
+                .map_err(serde::de::Error::custom)?;
+        }
+        Ok(bindings)
+    }
+}
+
+impl KeyBindings {
+    /// Each action, with its name in the config and its keys.
+    pub fn actions(&self) -> Vec<(Action, String, &Vec<InputKey>)> {
+        let named = [
+            (Action::MoveLeft, "move_left", &self.move_left),
+            (Action::MoveRight, "move_right", &self.move_right),
+            (Action::Jump, "jump", &self.jump),
+            (Action::Primary, "primary", &self.primary),
+            (Action::Secondary, "secondary", &self.secondary),
+            (Action::HotbarNext, "hotbar_next", &self.hotbar_next),
+            (Action::HotbarPrev, "hotbar_prev", &self.hotbar_prev),
+            (Action::Inventory, "inventory", &self.inventory),
+            (Action::Chat, "chat", &self.chat),
+            (Action::ToggleDebug, "toggle_debug", &self.toggle_debug),
+        ];
+        let mut out: Vec<_> = named
+            .into_iter()
+            .map(|(action, name, keys)| (action, String::from(name), keys))
+            .collect();
+        for (i, keys) in self.hotbar.iter().enumerate() {
+            out.push((Action::Hotbar(i as u8), format!("hotbar_{}", i + 1), keys));
+        }
+        out
+    }
+
+    /// Rebinds the action named ``name`` to ``keys``, like "jump" or "hotbar_3".
+    pub fn rebind(&mut self, name: &str, keys: Vec<InputKey>) -> Result<(), String> {
+        let slot = match name {
+            "move_left" => &mut self.move_left,
+            "move_right" => &mut self.move_right,
+            "jump" => &mut self.jump,
+            "primary" => &mut self.primary,
+            "secondary" => &mut self.secondary,
+            "hotbar_next" => &mut self.hotbar_next,
+            "hotbar_prev" => &mut self.hotbar_prev,
+            "inventory" => &mut self.inventory,
+            "chat" => &mut self.chat,
+            "toggle_debug" => &mut self.toggle_debug,
+            _ => {
+                let slot = name
+                    .strip_prefix("hotbar_")
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|n| (1..=HOTBAR_SIZE).contains(n))
+                    .ok_or_else(|| format!("unknown action {name:?}"))?;
+                &mut self.hotbar[slot - 1]
+            }
+        };
+        *slot = keys;
+        Ok(())
+    }
+
+    /// Returns which action each bound key triggers, or an error if a key is bound twice.
+    pub fn key_map(&self) -> Result<HashMap<InputKey, Action>, String> {
+        let mut map = HashMap::new();
+        let mut names = HashMap::new();
+        for (action, name, keys) in self.actions() {
+            for &key in keys {
+                if let Some(other) = names.insert(key, name.clone()) {
+                    return Err(format!("{key:?} is bound to both {other} and {name}"));
+                }
+                map.insert(key, action);
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// Parses a key name as written in the config, like "W", "Space" or "LeftClick".
+pub fn parse_key(name: &str) -> Result<InputKey, String> {
+    toml::Value::String(String::from(name))
+        .try_into()
+        .map_err(|_| format!("unknown key {name:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_uses_the_same_names_as_bind() {
+        let bindings: KeyBindings =
+            toml::from_str("jump = [\"Space\"]\nhotbar_3 = [\"Q\"]").unwrap();
+        assert_eq!(bindings.jump, vec![InputKey::Space]);
+        assert_eq!(bindings.hotbar[2], vec![InputKey::Q]);
+        assert_eq!(bindings.hotbar[0], vec![InputKey::Key1]);
+        assert!(toml::from_str::<KeyBindings>("hotbar_11 = [\"Q\"]").is_err());
+        assert!(toml::from_str::<KeyBindings>("fly = [\"Q\"]").is_err());
+    }
+}
@@ -1,17 +1,23 @@
+pub mod bot;
+pub mod config;
 mod game_frame;
 mod game_render;
 mod game_update;
 pub mod input_event;
+pub mod key_bindings;
+mod snapshot;
 
 use crossbeam_channel::{Receiver, Sender};
 use glutin::{NotCurrent, WindowedContext};
-use std::net::UdpSocket;
+use std::net::{SocketAddr, UdpSocket};
 use std::{thread, thread::JoinHandle};
 
+use self::config::*;
 use self::game_frame::*;
 use self::game_render::*;
 use self::game_update::*;
 use self::input_event::*;
+use crate::game::humanoid::*;
 use crate::game::net::*;
 use crate::net::*;
 use crate::time::*;
@@ -26,6 +32,7 @@ pub fn time<T>(t: &mut u64, f: impl FnOnce() -> T) -> T {
 pub fn launch_client(
     windowed_context: WindowedContext<NotCurrent>,
     input_recv: Receiver<InputEvent>,
+    config: ClientConfig,
 ) -> (JoinHandle<()>, JoinHandle<()>) {
     let (render_send, render_recv) = crossbeam_channel::unbounded();
 
@@ -33,7 +40,9 @@ pub fn launch_client(
     let glutin::dpi::PhysicalSize { width, height } = windowed_context.window().inner_size();
     let update_handle = thread::Builder::new()
         .name(String::from("client_update_thread"))
-        .spawn(move || client_update_thread(render_send, input_recv, (width as _, height as _)))
+        .spawn(move || {
+            client_update_thread(render_send, input_recv, (width as _, height as _), config)
+        })
         .unwrap();
 
     // Spawn client render thread.
@@ -49,49 +58,41 @@ pub fn client_update_thread(
     render_send: Sender<GameFrame>,
     input_recv: Receiver<InputEvent>,
     (window_w, window_h): (f32, f32),
+    config: ClientConfig,
 ) {
     println!("[Client] Update thread start.");
 
+    // Connect to the server. Returning drops render_send, which closes the window.
+    let mut net_events = vec![]; // events recv from server
+    let (mut endpoint, server_addr, (world_w, world_h, player_id)) =
+        match connect(&config, &mut net_events) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("[Client] {e}");
+                return;
+            }
+        };
+
     // Debug.
     let mut print_acc = 0;
     let mut preframe_us = 0u64;
     let mut step_us = 0u64;
     let mut postframe_us = 0u64;
 
-    // Create a server.
-    let mut net_events = vec![]; // events recv from server
-    let server_port = 0xCAFE;
-    let server_ip = "127.0.0.1";
-    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    socket.connect((server_ip, server_port)).unwrap();
-    socket.set_nonblocking(true).unwrap();
-
-    // Connect protocol.
-    let (world_w, world_h, player_id) = 'v: loop {
-        // Send connect request.
-        send(&socket, &[NetEvent::Connect]);
-
-        // Wait 1s.
-        std::thread::sleep(std::time::Duration::from_millis(300));
-
-        // Check socket for data.
-        recv(&socket, &mut net_events);
-
-        // The next message must be Accept.
-        if let Some(&NetEvent::Accept(world_w, world_h, player_id)) = net_events.get(0) {
-            net_events.remove(0);
-            break 'v (world_w, world_h, player_id);
-        }
-
-        // Clear, try again.
-        net_events.clear();
-    };
-
     // Create client state.
-    let mut game_update = GameUpdate::new(window_w, window_h, world_w, world_h, player_id);
+    let interpolation_delay_us = config.interpolation_delay_ms * 1_000;
+    let mut game_update = GameUpdate::new(
+        window_w,
+        window_h,
+        world_w,
+        world_h,
+        player_id,
+        interpolation_delay_us,
+        config.bindings.key_map().unwrap(),
+    );
 
     // Time keeping.
-    let frametime = 16_666; // us
+    let frametime = INPUT_FRAMETIME_US; // One input per frame.
     let mut timestamp = get_microseconds_as_u64();
 
     loop {
@@ -100,11 +101,14 @@ pub fn client_update_thread(
 
         // Run preframe.
         time(&mut preframe_us, || {
-            recv(&socket, &mut net_events);
+            endpoint.recv_from(&mut net_events);
             game_update.preframe(
                 timestamp,
                 input_recv.try_iter(),
-                std::mem::take(&mut net_events).into_iter(),
+                std::mem::take(&mut net_events)
+                    .into_iter()
+                    .filter(|&(_, src)| src == server_addr)
+                    .map(|(event, _)| event),
             );
         });
 
@@ -121,8 +125,11 @@ pub fn client_update_thread(
         // Run postframe.
         let (frame, net_events) = time(&mut postframe_us, || game_update.postframe(timestamp));
 
-        // Send net messages.
-        send(&socket, &net_events);
+        // Send net messages. Missing one would leave us out of sync, so give up instead.
+        if let Err(e) = endpoint.send_to(server_addr, net_events) {
+            println!("[Client] Could not send to the server: {e}");
+            break;
+        }
 
         // Send frame to render thread.
         match frame {
@@ -143,13 +150,101 @@ pub fn client_update_thread(
         }
     }
 
-    // Send kill.
-    send(&socket, &[NetEvent::Close]);
+    // Leave.
+    let _ = endpoint.send_to(server_addr, &[NetEvent::Disconnect]);
 
     println!("[Client] Update thread closed.");
     return;
 }
 
+/// Binds a socket and performs the connect protocol, giving up after the configured timeout or
+/// if the server rejects us. Any events received after the Accept are left in ``net_events``.
+fn connect(
+    config: &ClientConfig,
+    net_events: &mut Vec<(NetEvent, SocketAddr)>,
+) -> Result<(Endpoint, SocketAddr, (u16, u16, u64)), String> {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    use std::io::ErrorKind;
+
+    let server_addr = config.resolve_server()?;
+    let bind_addr = match server_addr {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(bind_addr).map_err(|e| format!("could not bind socket: {e}"))?;
+    socket
+        .connect(server_addr)
+        .map_err(|e| format!("could not connect to {server_addr}: {e}"))?;
+    socket.set_nonblocking(true).unwrap();
+    let mut endpoint = Endpoint::new(socket, config.timeout_ms);
+
+    // Replies must echo this, so stale or forged ones are ignored.
+    let nonce = RandomState::new().build_hasher().finish();
+    let connect_event = NetEvent::Connect(
+        PROTOCOL_VERSION,
+        content_hash(),
+        nonce,
+        config.player_name.clone(),
+    );
+
+    // Connect protocol.
+    println!("[Client] Connecting to {server_addr}.");
+    let deadline = get_milliseconds_as_u64() + config.connect_timeout_ms;
+    while get_milliseconds_as_u64() < deadline {
+        // Send connect request.
+        endpoint
+            .send_to(server_addr, &[connect_event.clone()])
+            .map_err(|e| format!("could not send connect: {e}"))?;
+
+        // Wait 300ms.
+        std::thread::sleep(std::time::Duration::from_millis(300));
+
+        // An unreachable port shows up as an error on the next read.
+        if let Err(e) = endpoint.socket().peek(&mut [0u8; 1]) {
+            if e.kind() == ErrorKind::ConnectionRefused {
+                return Err(format!(
+                    "{server_addr} refused the connection (is the server running?)"
+                ));
+            }
+        }
+
+        // Check socket for data.
+        endpoint.recv_from(net_events);
+        net_events.retain(|&(_, src)| src == server_addr);
+
+        // Give up if rejected.
+        let reject = net_events.iter().find_map(|(e, _)| match e {
+            NetEvent::Reject(n, reason) if *n == nonce => Some(reason),
+            _ => None,
+        });
+        if let Some(reason) = reject {
+            return Err(format!("rejected by {server_addr}: {reason}"));
+        }
+
+        // Wait for the Accept, anything before it is stale.
+        let accept = net_events
+            .iter()
+            .position(|(e, _)| matches!(e, NetEvent::Accept(n, ..) if *n == nonce));
+        if let Some(index) = accept {
+            let Some((NetEvent::Accept(_, world_w, world_h, player_id), _)) =
+                net_events.drain(..=index).last()
+            else {
+                unreachable!()
+            };
+            return Ok((endpoint, server_addr, (world_w, world_h, player_id)));
+        }
+
+        // Clear, try again.
+        net_events.clear();
+    }
+
+    Err(format!(
+        "no response from {server_addr} after {}ms",
+        config.connect_timeout_ms
+    ))
+}
+
 pub fn client_render_thread(
     windowed_context: WindowedContext<NotCurrent>,
     render_recv: Receiver<GameFrame>,
@@ -160,12 +255,6 @@ pub fn client_render_thread(
     let windowed_context = unsafe {
         let ctx = windowed_context.make_current().unwrap();
         ezgl::gl::load_with(|s| ctx.get_proc_address(s) as *const _);
-        ezgl::gl::ClearColor(
-            0x15 as f32 / 256.,
-            0x9F as f32 / 256.,
-            0xEA as f32 / 256.,
-            1.,
-        );
         ezgl::bind_vao();
         ctx
     };
@@ -173,8 +262,11 @@ pub fn client_render_thread(
     // Initialize render state.
     let mut game_render = unsafe { GameRender::new() };
 
-    // Wait on current frame.
-    let mut current_frame = render_recv.recv().unwrap();
+    // Wait on current frame (the update thread may give up before sending one).
+    let Ok(mut current_frame) = render_recv.recv() else {
+        println!("[Client] Render thread closed.");
+        return;
+    };
 
     loop {
         // Get most recent frame.
@@ -184,6 +276,13 @@ pub fn client_render_thread(
             Err(crossbeam_channel::TryRecvError::Disconnected) => break, // channel closed
         };
 
+        // Some platforms need the context resized along with the window.
+        let (w, h) = (current_frame.view_w as u32, current_frame.view_h as u32);
+        let size = glutin::dpi::PhysicalSize::new(w, h);
+        if size != windowed_context.window().inner_size() {
+            windowed_context.resize(size);
+        }
+
         // Render frame.
         unsafe {
             game_render.render(&current_frame);
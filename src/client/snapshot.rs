@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::game::humanoid::*;
+use crate::game::item::*;
+
+// Snapshots kept, at most. A few seconds at the server's usual tick rate.
+const MAX_SNAPSHOTS: usize = 32;
+
+// How far past the newest snapshot entities keep moving (in us), if the next one is late.
+const MAX_EXTRAPOLATION_US: u64 = 250_000;
+
+// How quickly the estimated server clock drifts back after a packet arrives unusually early.
+const CLOCK_DRIFT: f64 = 0.05;
+
+/// Something that can be drawn between two snapshots.
+pub trait Interpolate: Clone {
+    /// Blends from ``self`` (t = 0) to ``other`` (t = 1).
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+
+    /// Moves ``self`` forward by ``dt`` seconds.
+    fn extrapolate(&self, dt: f32) -> Self;
+}
+
+impl Interpolate for HumanoidPhysics {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        Self {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            dx: self.dx + (other.dx - self.dx) * t,
+            dy: self.dy + (other.dy - self.dy) * t,
+            grounded: if t < 0.5 {
+                self.grounded
+            } else {
+                other.grounded
+            },
+        }
+    }
+
+    fn extrapolate(&self, dt: f32) -> Self {
+        Self {
+            x: self.x + self.dx * dt,
+            y: self.y + self.dy * dt,
+            ..*self
+        }
+    }
+}
+
+impl Interpolate for Item {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        Self {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            dx: self.dx + (other.dx - self.dx) * t,
+            dy: self.dy + (other.dy - self.dy) * t,
+            ..*self
+        }
+    }
+
+    fn extrapolate(&self, dt: f32) -> Self {
+        Self {
+            x: self.x + self.dx * dt,
+            y: self.y + self.dy * dt,
+            ..*self
+        }
+    }
+}
+
+/// Timestamped server snapshots of a set of entities, which are drawn ``delay_us`` in the past so
+/// there is (usually) a snapshot on either side to interpolate between.
+pub struct SnapshotBuffer<T: Interpolate> {
+    snapshots: VecDeque<(u64, BTreeMap<u64, T>)>, // Server timestamp (in us), oldest first.
+    clock_offset_us: Option<f64>,                 // Estimated server clock minus ours.
+    delay_us: u64,
+}
+
+impl<T: Interpolate> SnapshotBuffer<T> {
+    pub fn new(delay_us: u64) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            clock_offset_us: None,
+            delay_us,
+        }
+    }
+
+    /// Stores a snapshot taken at ``server_us``, received at ``local_us``.
+    pub fn push(&mut self, local_us: u64, server_us: u64, entities: BTreeMap<u64, T>) {
+        // The least delayed packet gives the best estimate of the server clock.
+        let offset = server_us as f64 - local_us as f64;
+        self.clock_offset_us = Some(match self.clock_offset_us {
+            Some(old) if offset <= old => old + (offset - old) * CLOCK_DRIFT,
+            _ => offset,
+        });
+
+        // Keep snapshots sorted, skipping duplicates.
+        let index = self.snapshots.partition_point(|&(t, _)| t < server_us);
+        if self
+            .snapshots
+            .get(index)
+            .map_or(false, |&(t, _)| t == server_us)
+        {
+            return;
+        }
+        self.snapshots.insert(index, (server_us, entities));
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Returns the entities as they should be drawn at ``local_us``, if any snapshot has arrived.
+    pub fn sample(&mut self, local_us: u64) -> Option<BTreeMap<u64, T>> {
+        let offset = self.clock_offset_us?;
+        let render_us = (local_us as f64 + offset - self.delay_us as f64).max(0.) as u64;
+
+        // Snapshots before the one just older than render_us aren't needed anymore.
+        while self.snapshots.len() > 2 && self.snapshots[1].0 <= render_us {
+            self.snapshots.pop_front();
+        }
+
+        let (t0, a) = self.snapshots.front()?;
+        let next = self
+            .snapshots
+            .get(1)
+            .filter(|&&(t1, _)| *t0 <= render_us && render_us < t1);
+        let out = match next {
+            // Between two snapshots. Entities only in the older one stay put.
+            Some((t1, b)) => {
+                let t = (render_us - t0) as f32 / (t1 - t0) as f32;
+                let entities = a.iter().map(|(&id, e)| match b.get(&id) {
+                    Some(other) => (id, e.interpolate(other, t)),
+                    None => (id, e.clone()),
+                });
+                entities.collect()
+            }
+
+            // Past the newest snapshot, so one must be late or lost.
+            None if *t0 <= render_us => {
+                let (t0, a) = self.snapshots.back()?;
+                let dt_us = render_us.saturating_sub(*t0).min(MAX_EXTRAPOLATION_US);
+                let dt = dt_us as f32 / 1_000_000.;
+                a.iter().map(|(&id, e)| (id, e.extrapolate(dt))).collect()
+            }
+
+            // Before the oldest snapshot.
+            None => a.clone(),
+        };
+
+        Some(out)
+    }
+}
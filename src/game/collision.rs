@@ -39,7 +39,7 @@ pub fn collect_newly_colliding_tiles_x(
     let (w, h) = tiles.size();
     for_each_sub_wrapping(w, h, x1..x2, y1..y2, |_x, _y, index| {
         let tile = tiles[index];
-        if !matches!(tile, Tile::None) {
+        if tile.is_solid() {
             vec.push(tile);
         }
     });
@@ -99,7 +99,7 @@ pub fn collect_newly_colliding_tiles_y(
     let (w, h) = tiles.size();
     for_each_sub_wrapping(w, h, x1..x2, y1..y2, |_x, _y, index| {
         let tile = tiles[index];
-        if !matches!(tile, Tile::None) {
+        if tile.is_solid() {
             vec.push(tile);
         }
     });
@@ -153,6 +153,22 @@ pub fn resolve_humanoid_tile_collision_y(
     physics.dy = corrected_dy;
 }
 
+/// Corrects the x position of a Item that is colliding with tiles.
+pub fn resolve_item_tile_collision_x(item: &mut Item, tile_x: usize, tiles: &Vec<Tile>) {
+    let (mut corrected_x, mut corrected_dx) = (item.x, item.dx);
+    for _ in tiles {
+        // correct position
+        if item.dx > 0. {
+            corrected_x = (tile_x * TILE_SIZE - 16) as f32;
+        } else {
+            corrected_x = (tile_x * TILE_SIZE + TILE_SIZE) as f32;
+        }
+        corrected_dx = 0.0;
+    }
+    item.x = corrected_x;
+    item.dx = corrected_dx;
+}
+
 /// Corrects the y position of a Item that is colliding with tiles.
 pub fn resolve_item_tile_collision_y(item: &mut Item, tile_y: usize, tiles: &Vec<Tile>) {
     let (mut corrected_y, mut corrected_dy) = (item.y, item.dy);
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::game::humanoid::*;
+use crate::game::item::*;
+
+// Quantized units per pixel (for positions) and per pixel per second (for velocities).
+const SCALE: f32 = 16.;
+
+#[inline(always)]
+fn quantize_position(v: f32) -> i32 {
+    (v * SCALE).round() as i32
+}
+
+#[inline(always)]
+fn quantize_velocity(v: f32) -> i16 {
+    (v * SCALE).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// A humanoid as sent to other players. Exact enough to draw, and it compares equal when nothing
+/// visible has changed.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QuantizedHumanoid {
+    x: i32,
+    y: i32,
+    dx: i16,
+    dy: i16,
+    grounded: bool,
+}
+
+impl From<&HumanoidPhysics> for QuantizedHumanoid {
+    fn from(physics: &HumanoidPhysics) -> Self {
+        Self {
+            x: quantize_position(physics.x),
+            y: quantize_position(physics.y),
+            dx: quantize_velocity(physics.dx),
+            dy: quantize_velocity(physics.dy),
+            grounded: physics.grounded,
+        }
+    }
+}
+
+impl From<&QuantizedHumanoid> for HumanoidPhysics {
+    fn from(q: &QuantizedHumanoid) -> Self {
+        Self {
+            x: q.x as f32 / SCALE,
+            y: q.y as f32 / SCALE,
+            dx: q.dx as f32 / SCALE,
+            dy: q.dy as f32 / SCALE,
+            grounded: q.grounded,
+        }
+    }
+}
+
+/// An item as sent to players.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QuantizedItem {
+    id: ItemId,
+    count: u16,
+    x: i32,
+    y: i32,
+    dx: i16,
+    dy: i16,
+}
+
+impl From<&Item> for QuantizedItem {
+    fn from(item: &Item) -> Self {
+        Self {
+            id: item.id,
+            count: item.count,
+            x: quantize_position(item.x),
+            y: quantize_position(item.y),
+            dx: quantize_velocity(item.dx),
+            dy: quantize_velocity(item.dy),
+        }
+    }
+}
+
+impl From<&QuantizedItem> for Item {
+    fn from(q: &QuantizedItem) -> Self {
+        Self {
+            id: q.id,
+            count: q.count,
+            age_us: 0,
+            x: q.x as f32 / SCALE,
+            y: q.y as f32 / SCALE,
+            dx: q.dx as f32 / SCALE,
+            dy: q.dy as f32 / SCALE,
+        }
+    }
+}
+
+/// Every replicated entity, as of one server tick.
+#[derive(Clone, Debug, Default)]
+pub struct EntityState {
+    pub humanoids: BTreeMap<u64, QuantizedHumanoid>,
+    pub items: BTreeMap<u64, QuantizedItem>,
+}
+
+/// The entities spawned or changed, and those despawned, between two maps.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapDelta<T> {
+    pub changed: Vec<(u64, T)>,
+    pub removed: Vec<u64>,
+}
+
+impl<T: Copy + PartialEq> MapDelta<T> {
+    pub fn diff(baseline: &BTreeMap<u64, T>, current: &BTreeMap<u64, T>) -> Self {
+        let changed = current
+            .iter()
+            .filter(|&(id, v)| baseline.get(id) != Some(v))
+            .map(|(&id, &v)| (id, v))
+            .collect();
+        let removed = baseline
+            .keys()
+            .filter(|id| !current.contains_key(id))
+            .copied()
+            .collect();
+        Self { changed, removed }
+    }
+
+    pub fn apply(&self, baseline: &BTreeMap<u64, T>) -> BTreeMap<u64, T> {
+        let mut out = baseline.clone();
+        for id in &self.removed {
+            out.remove(id);
+        }
+        out.extend(self.changed.iter().copied());
+        out
+    }
+}
+
+/// One tick of entity state, relative to a snapshot the receiver already has (or to nothing, if
+/// ``baseline`` is None).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotDelta {
+    pub id: u32,
+    pub baseline: Option<u32>,
+    pub timestamp_us: u64,
+    pub input_ack: Option<u32>, // The receiver's last input frame applied.
+    pub player: Option<HumanoidPhysics>, // The receiver's humanoid, unquantized for reconciling.
+    pub humanoids: MapDelta<QuantizedHumanoid>,
+    pub items: MapDelta<QuantizedItem>,
+}
+
+impl EntityState {
+    /// Returns the changes from ``baseline`` to ``self``.
+    pub fn diff(
+        &self,
+        baseline: &EntityState,
+    ) -> (MapDelta<QuantizedHumanoid>, MapDelta<QuantizedItem>) {
+        (
+            MapDelta::diff(&baseline.humanoids, &self.humanoids),
+            MapDelta::diff(&baseline.items, &self.items),
+        )
+    }
+
+    /// Rebuilds the state a snapshot describes, from the state of its baseline.
+    pub fn apply(&self, delta: &SnapshotDelta) -> EntityState {
+        EntityState {
+            humanoids: delta.humanoids.apply(&self.humanoids),
+            items: delta.items.apply(&self.items),
+        }
+    }
+}
@@ -3,9 +3,13 @@ pub const HUMANOID_MAX_VELOCITY: f32 = 900.; // Movement above 15 pixels per fra
                                              // cause problems.
 pub const HUMANOID_WIDTH: usize = 32 - 4;
 pub const HUMANOID_HEIGHT: usize = 48 - 4;
+pub const HUMANOID_REACH: f32 = 6. * 16.; // How far from its center a humanoid can edit tiles.
+pub const HAND_MINING_POWER: u16 = 4; // Hardness mined per second without a tool.
 
 pub use serde::{Deserialize, Serialize};
 
+use crate::game::collision::*;
+
 #[derive(Copy, Clone, Debug)]
 pub enum HumanoidActionState {
     Idle,
@@ -55,3 +59,115 @@ pub fn update_humanoid_physics_y(dt: f32, physics: &mut HumanoidPhysics, ddy: f3
         .dy
         .clamp(-HUMANOID_MAX_VELOCITY, HUMANOID_MAX_VELOCITY);
 }
+
+/// Returns true if the center of tile (``x``, ``y``) is within ``reach`` pixels of the center of a
+/// humanoid at ``physics``.
+pub fn can_reach(physics: &HumanoidPhysics, x: usize, y: usize, reach: f32) -> bool {
+    let dx = (x * TILE_SIZE + TILE_SIZE / 2) as f32 - (physics.x + HUMANOID_WIDTH as f32 / 2.);
+    let dy = (y * TILE_SIZE + TILE_SIZE / 2) as f32 - (physics.y + HUMANOID_HEIGHT as f32 / 2.);
+    dx.hypot(dy) <= reach
+}
+
+/// Returns true if a humanoid at ``physics`` overlaps tile (``x``, ``y``).
+pub fn overlaps_tile(physics: &HumanoidPhysics, x: usize, y: usize) -> bool {
+    let (tx, ty) = ((x * TILE_SIZE) as f32, (y * TILE_SIZE) as f32);
+    physics.x < tx + TILE_SIZE as f32
+        && tx < physics.x + HUMANOID_WIDTH as f32
+        && physics.y < ty + TILE_SIZE as f32
+        && ty < physics.y + HUMANOID_HEIGHT as f32
+}
+
+/// A tile a humanoid is mining, and for how long it has been (in us).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Mining {
+    pub x: u16,
+    pub y: u16,
+    pub foreground: bool,
+    pub progress_us: u64,
+}
+
+/// How long it takes to mine ``tile`` while holding ``tool`` (in us).
+pub fn mining_time_us(tile: Tile, tool: Option<ItemId>) -> u64 {
+    let power = tool
+        .and_then(|id| id.properties().mining_power)
+        .unwrap_or(HAND_MINING_POWER);
+    tile.properties().hardness as u64 * 1_000_000 / power as u64
+}
+
+/// Every input command covers one client frame of this length (in us).
+pub const INPUT_FRAMETIME_US: u64 = 16_666;
+
+/// One frame of player input. Clients send these instead of their physics, and the server replays
+/// them with step_humanoid.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HumanoidInput {
+    pub frame: u32, // The client frame this was sampled on, counting up from 0.
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool, // Only on the frame jump was pressed.
+}
+
+/// Advances a humanoid by ``dt`` seconds under ``input``, colliding with ``tiles``.
+pub fn step_humanoid(
+    dt: f32,
+    physics: &mut HumanoidPhysics,
+    input: &HumanoidInput,
+    tiles: &impl Index2d<usize, Output = Tile>,
+    tmp: &mut Vec<Tile>,
+) {
+    // Cancel all acceleration.
+    let mut ddx = 0.;
+    let mut ddy = HUMANOID_GRAVITY;
+
+    // Move right.
+    if input.right && !input.left && physics.dx < 9. * 16. {
+        ddx += 16. * 16.;
+    }
+    // Move left.
+    if input.left && !input.right && physics.dx > -9. * 16. {
+        ddx += -16. * 16.;
+    }
+    // Else friction?
+    if !(input.right && physics.dx > 0.0 || input.left && physics.dx < 0.0) {
+        ddx += -physics.dx * 4.;
+    }
+
+    // Jump.
+    if input.jump && physics.grounded {
+        ddy += -12000.;
+    }
+
+    // Update physics (y).
+    let last_y = physics.y;
+    update_humanoid_physics_y(dt, physics, ddy);
+
+    // Calculate tiles that are now colliding, and resolve them.
+    tmp.clear();
+    let ty = collect_newly_colliding_tiles_y(
+        last_y,
+        physics.x,
+        physics.y,
+        HUMANOID_WIDTH as f32,
+        HUMANOID_HEIGHT as f32,
+        tiles,
+        tmp,
+    );
+    resolve_humanoid_tile_collision_y(physics, ty, tmp);
+
+    // Update physics (x).
+    let last_x = physics.x;
+    update_humanoid_physics_x(dt, physics, ddx);
+
+    // Calculate tiles that are now colliding, and resolve them.
+    tmp.clear();
+    let tx = collect_newly_colliding_tiles_x(
+        last_x,
+        physics.x,
+        physics.y,
+        HUMANOID_WIDTH as f32,
+        HUMANOID_HEIGHT as f32,
+        tiles,
+        tmp,
+    );
+    resolve_humanoid_tile_collision_x(physics, tx, tmp);
+}
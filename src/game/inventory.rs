@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::item::*;
+
+pub const INVENTORY_SIZE: usize = 40;
+pub const HOTBAR_SIZE: usize = 10; // The first slots, which can be selected for use.
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ItemStack {
+    pub id: ItemId,
+    pub count: u16,
+}
+
+/// Something a player does to their own inventory. Slots out of range are ignored.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum InventoryAction {
+    Move(u8, u8, u16), // from, to, count. Onto an empty slot or a stack of the same item.
+    Split(u8, u8),     // from, to. Moves half a stack onto an empty slot.
+    Swap(u8, u8),
+}
+
+/// A player's items. The server owns it, clients get a copy whenever it changes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Inventory {
+    pub slots: Vec<Option<ItemStack>>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![None; INVENTORY_SIZE],
+        }
+    }
+
+    pub fn get(&self, slot: usize) -> Option<ItemStack> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// Returns true if at least one ``id`` would fit.
+    pub fn can_fit(&self, id: ItemId) -> bool {
+        self.slots.iter().any(|slot| match slot {
+            Some(stack) => stack.id == id && stack.count < id.max_stack(),
+            None => true,
+        })
+    }
+
+    /// Adds ``count`` of ``id``, topping up existing stacks before starting new ones. Returns how
+    /// many didn't fit.
+    pub fn add(&mut self, id: ItemId, mut count: u16) -> u16 {
+        let max = id.max_stack();
+        for stack in self.slots.iter_mut().flatten().filter(|s| s.id == id) {
+            let n = count.min(max.saturating_sub(stack.count));
+            stack.count += n;
+            count -= n;
+        }
+        for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+            if count == 0 {
+                break;
+            }
+            let n = count.min(max);
+            *slot = Some(ItemStack { id, count: n });
+            count -= n;
+        }
+        count
+    }
+
+    /// Moves up to ``count`` items from one slot to another. Returns true if anything moved.
+    pub fn move_items(&mut self, from: usize, to: usize, count: u16) -> bool {
+        if from == to || to >= self.slots.len() {
+            return false;
+        }
+        let Some(src) = self.get(from) else {
+            return false;
+        };
+        let count = match self.slots[to] {
+            None => count.min(src.count).min(src.id.max_stack()),
+            Some(dst) if dst.id == src.id => {
+                count.min(src.count).min(dst.id.max_stack() - dst.count)
+            }
+            Some(_) => return false,
+        };
+        if count == 0 {
+            return false;
+        }
+
+        let dst = self.slots[to].get_or_insert(ItemStack {
+            id: src.id,
+            count: 0,
+        });
+        dst.count += count;
+        self.slots[from] = match src.count - count {
+            0 => None,
+            n => Some(ItemStack { count: n, ..src }),
+        };
+        true
+    }
+
+    /// Applies ``action``, returning true if anything changed.
+    pub fn apply(&mut self, action: InventoryAction) -> bool {
+        match action {
+            InventoryAction::Move(from, to, count) => self.move_items(from as _, to as _, count),
+            InventoryAction::Split(from, to) => {
+                let half = self.get(from as _).map_or(0, |stack| stack.count / 2);
+                self.get(to as _).is_none() && self.move_items(from as _, to as _, half)
+            }
+            InventoryAction::Swap(a, b) => {
+                let (a, b) = (a as usize, b as usize);
+                if a == b || a >= self.slots.len() || b >= self.slots.len() {
+                    return false;
+                }
+                self.slots.swap(a, b);
+                true
+            }
+        }
+    }
+
+    /// Removes one item from ``slot``, returning what it was.
+    pub fn take_one(&mut self, slot: usize) -> Option<ItemId> {
+        let stack = self.slots.get_mut(slot)?;
+        let id = stack.as_ref()?.id;
+        match stack {
+            Some(s) if s.count > 1 => s.count -= 1,
+            _ => *stack = None,
+        }
+        Some(id)
+    }
+}
@@ -1,17 +1,61 @@
 pub const ITEM_GRAVITY: f32 = 9.8 * 16.;
 pub const ITEM_MAX_VELOCITY: f32 = 900.;
+pub const ITEM_DRAG: f32 = 4.; // Fraction of its x velocity an item loses per second (roughly).
+pub const ITEM_DROP_SPEED: f32 = 4. * 16.; // How hard dropped items are tossed.
+pub const ITEM_SIZE: f32 = 16.;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
-pub enum ItemId {
-    Dirt,
-    Stone,
+use crate::game::registry::*;
+use crate::game::tile::*;
+
+/// An item id. What each one is comes from the registry.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "u16", into = "u16")]
+pub struct ItemId(pub u16);
+
+impl ItemId {
+    pub fn properties(self) -> &'static ItemProperties {
+        &REGISTRY.items[self.0 as usize]
+    }
+
+    /// The item a broken tile drops, if any.
+    pub fn dropped_by(tile: Tile) -> Option<ItemId> {
+        tile.properties().drop
+    }
+
+    /// The most of this item one stack can hold.
+    pub fn max_stack(self) -> u16 {
+        self.properties().max_stack
+    }
+
+    /// The tile this item places, if any.
+    pub fn placed_tile(self) -> Option<Tile> {
+        self.properties().places
+    }
+}
+
+impl TryFrom<u16> for ItemId {
+    type Error = String;
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match (value as usize) < REGISTRY.items.len() {
+            true => Ok(ItemId(value)),
+            false => Err(format!("unknown item {value}")),
+        }
+    }
+}
+
+impl From<ItemId> for u16 {
+    fn from(id: ItemId) -> u16 {
+        id.0
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Item {
     pub id: ItemId,
+    pub count: u16,
+    pub age_us: u64, // Time spent on the ground. Only the server keeps track of this.
     pub x: f32,
     pub y: f32,
     pub dx: f32,
@@ -1,5 +1,6 @@
+use std::collections::VecDeque;
+
 use crate::array2d::*;
-use crate::common::*;
 use crate::game::tile::*;
 
 // Some lighting constants
@@ -8,104 +9,305 @@ pub const MIN_BRIGHTNESS: u8 = 0; // Value representing maximal darkness.
 pub const MAX_FADE: u8 = MAX_BRIGHTNESS;
 pub const MIN_FADE: u8 = 1; // Least amount of fade that can occur.
 pub const TRANSPARENT_FADE: u8 = MIN_FADE; // Fade of free space.
-pub const OPAQUE_FADE: u8 = TRANSPARENT_FADE * 3; // Fade of solid blocks.
 pub const MAX_LIGHT_DISTANCE: usize = (MAX_BRIGHTNESS / MIN_FADE) as usize; // The furthest a light source can reach (in tiles).
 
-#[inline(always)]
-pub fn gen_fade_map(
-    (view_x, view_y): (usize, usize),
-    foreground_tiles: &FastArray2D<Tile>,
-    background_tiles: &FastArray2D<Tile>,
-    mut fade_map: &mut Array2D<u8>,
-    mut light_map_r: &mut Array2D<u8>,
-    mut light_map_g: &mut Array2D<u8>,
-    mut light_map_b: &mut Array2D<u8>,
-) -> impl Iterator<Item = usize> + Clone {
-    let (w, h) = light_map_r.size();
-
-    // Record some view stuff
-    let x = ifdiv(view_x, TILE_SIZE).saturating_sub(MAX_LIGHT_DISTANCE);
-    let y = ifdiv(view_y, TILE_SIZE).saturating_sub(MAX_LIGHT_DISTANCE);
-
-    // Set up the fade_map from tile data
-    let mut light_queue = Vec::with_capacity(1024);
-    let (tw, th) = foreground_tiles.size();
-    let m = x + y * w;
-    crate::array2d::for_each_sub_wrapping(
-        tw,
-        th,
-        x + 1..x + w - 1,
-        y + 1..y + h - 1,
-        |x, y, index| {
-            let tile_index = index;
-            let light_index = x + y * w - m;
-
-            // get tile at this (x, y)
-            let fg_tile = foreground_tiles[tile_index];
-            let bg_tile = background_tiles[tile_index];
-
-            match (fg_tile, bg_tile) {
-                // For (air, air), update the light map and push a light probe
-                (Tile::None, Tile::None) => {
-                    light_map_r[light_index] = MAX_BRIGHTNESS;
-                    light_map_g[light_index] = MAX_BRIGHTNESS;
-                    light_map_b[light_index] = MAX_BRIGHTNESS;
-                    fade_map[light_index] = MIN_FADE;
-                    light_queue.push(light_index);
+/// Light over the same wrapping tile space as the client's tile arrays, so a loaded chunk keeps
+/// its light from frame to frame. Only the area around a changed tile or a moved light is relit.
+pub struct LightEngine {
+    pub rgb: [FastArray2D<u8>; 3],    // Brightness of each channel.
+    fade: FastArray2D<u8>,            // Light lost leaving each tile.
+    tile_light: FastArray2D<[u8; 3]>, // Light given off by each tile.
+    open: FastArray2D<bool>,          // Whether each tile is open to the sky.
+    sky: [u8; 3],                     // Light given off by open sky.
+    emitters: Vec<(usize, [u8; 3])>,  // Lights that aren't tiles, by index.
+}
+
+impl LightEngine {
+    /// Starts out as open sky everywhere, same as an empty tile array.
+    pub fn new(w_exp: usize, h_exp: usize) -> Self {
+        Self {
+            rgb: [0; 3].map(|_| FastArray2D::from_value(w_exp, h_exp, MAX_BRIGHTNESS)),
+            fade: FastArray2D::from_value(w_exp, h_exp, MIN_FADE),
+            tile_light: FastArray2D::from_value(w_exp, h_exp, [MIN_BRIGHTNESS; 3]),
+            open: FastArray2D::from_value(w_exp, h_exp, true),
+            sky: [MAX_BRIGHTNESS; 3],
+            emitters: Vec::new(),
+        }
+    }
+
+    /// Rereads the tiles in ``xr`` by ``yr`` (wrapping), and relights around any that changed.
+    pub fn update_tiles(
+        &mut self,
+        foreground_tiles: &FastArray2D<Tile>,
+        background_tiles: &FastArray2D<Tile>,
+        xr: std::ops::Range<usize>,
+        yr: std::ops::Range<usize>,
+    ) {
+        let (w, h) = self.fade.size();
+        let mut changed = Vec::new();
+        crate::array2d::for_each_sub_wrapping(w, h, xr, yr, |_, _, index| {
+            let (fg_tile, bg_tile) = (foreground_tiles[index], background_tiles[index]);
+            let (fade, light) = tile_light(fg_tile, bg_tile);
+            let open = fg_tile == Tile::NONE && bg_tile == Tile::NONE;
+            if fade != self.fade[index]
+                || light != self.tile_light[index]
+                || open != self.open[index]
+            {
+                self.fade[index] = fade;
+                self.tile_light[index] = light;
+                self.open[index] = open;
+                changed.push(index);
+            }
+        });
+        self.relight(&changed);
+    }
+
+    /// Changes how bright open sky is, like when the sun goes down. Relights every open tile.
+    pub fn set_sky(&mut self, (r, g, b): (u8, u8, u8)) {
+        if self.sky == [r, g, b] {
+            return;
+        }
+        self.sky = [r, g, b];
+        let (w, h) = self.fade.size();
+        let changed: Vec<usize> = (0..w * h).filter(|&i| self.open[i]).collect();
+        self.relight(&changed);
+    }
+
+    /// Replaces the lights that aren't tiles, like dropped torches, as (tile x, tile y, rgb).
+    pub fn set_emitters(
+        &mut self,
+        emitters: impl IntoIterator<Item = (usize, usize, (u8, u8, u8))>,
+    ) {
+        let (w, h) = self.fade.size();
+        let mut emitters: Vec<_> = emitters
+            .into_iter()
+            .map(|(x, y, (r, g, b))| ((x & (w - 1)) + (y & (h - 1)) * w, [r, g, b]))
+            .collect();
+        emitters.sort_unstable();
+
+        // Only relight where a light appeared, disappeared or changed color.
+        let mut changed: Vec<usize> = self
+            .emitters
+            .iter()
+            .filter(|e| !emitters.contains(e))
+            .chain(emitters.iter().filter(|e| !self.emitters.contains(e)))
+            .map(|&(index, _)| index)
+            .collect();
+        changed.sort_unstable();
+        changed.dedup();
+
+        self.emitters = emitters;
+        self.relight(&changed);
+    }
+
+    /// Lights everything from scratch. Slow, but what the incremental updates should always match.
+    pub fn recompute(&self) -> [FastArray2D<u8>; 3] {
+        let (w, h) = self.fade.size();
+        let (w_exp, h_exp) = (w.trailing_zeros() as usize, h.trailing_zeros() as usize);
+        [0, 1, 2].map(|c| {
+            let mut light_map = FastArray2D::from_value(w_exp, h_exp, MIN_BRIGHTNESS);
+            let mut queue = VecDeque::new();
+            for index in 0..w * h {
+                let light = self.source(index)[c];
+                if light > MIN_BRIGHTNESS {
+                    light_map[index] = light;
+                    queue.push_back(index);
                 }
-                // For (air, anything), make transparent fade
-                (Tile::None, _) => fade_map[light_index] = TRANSPARENT_FADE,
-                // Anything else, make solid fade
-                (_, _) => fade_map[light_index] = OPAQUE_FADE,
             }
-        },
-    );
+            flood(&mut light_map, &self.fade, queue);
+            light_map
+        })
+    }
 
-    light_queue.into_iter()
+    /// How many tiles (per channel) differ from a full recompute. Anything but 0 is a bug.
+    pub fn count_mismatches(&self) -> usize {
+        let (w, h) = self.fade.size();
+        let full = self.recompute();
+        (0..3)
+            .map(|c| (0..w * h).filter(|&i| full[c][i] != self.rgb[c][i]).count())
+            .sum()
+    }
+
+    // The brightest of the tile's light, the sky's if it's open, and any emitter's on it.
+    fn source(&self, index: usize) -> [u8; 3] {
+        let mut light = self.tile_light[index];
+        let sky = self.open[index].then_some((index, self.sky));
+        let emitters = self.emitters.iter().filter(|&&(i, _)| i == index);
+        for (_, emitted) in emitters.copied().chain(sky) {
+            for (l, e) in light.iter_mut().zip(emitted) {
+                *l = (*l).max(e);
+            }
+        }
+        light
+    }
+
+    // Relights after the fade or source of the ``changed`` tiles changed. First darkens every
+    // tile that could have been lit through them (anything dimmer than its neighbour on the way
+    // out), remembering the brighter tiles bordering that area. Then floods back in from those
+    // and from any sources that were darkened.
+    fn relight(&mut self, changed: &[usize]) {
+        if changed.is_empty() {
+            return;
+        }
+        let (w, h) = self.fade.size();
+        for c in 0..3 {
+            let light_map = &mut self.rgb[c];
+            let mut darken = VecDeque::new();
+            let mut brighten = VecDeque::new();
+            let mut darkened = Vec::new();
+
+            for &index in changed {
+                darken.push_back((index, light_map[index]));
+                light_map[index] = MIN_BRIGHTNESS;
+                darkened.push(index);
+            }
+            while let Some((index, old)) = darken.pop_front() {
+                for next_index in neighbours(w, h, index) {
+                    let brightness = light_map[next_index];
+                    if brightness == MIN_BRIGHTNESS {
+                        continue;
+                    }
+                    if brightness < old {
+                        light_map[next_index] = MIN_BRIGHTNESS;
+                        darken.push_back((next_index, brightness));
+                        darkened.push(next_index);
+                    } else {
+                        brighten.push_back(next_index);
+                    }
+                }
+            }
+
+            for index in darkened {
+                let light = self.source(index)[c];
+                let light_map = &mut self.rgb[c];
+                if light > light_map[index] {
+                    light_map[index] = light;
+                    brighten.push_back(index);
+                }
+            }
+            flood(&mut self.rgb[c], &self.fade, brighten);
+        }
+    }
 }
 
-/// Generates a lightmap from points
-#[inline(always)]
-pub fn propogate_light_map_unbounded(
-    mut light_map: impl Index2dMut<usize, Output = u8>,
-    fade_map: impl Index2d<usize, Output = u8>,
-    lights: impl IntoIterator<Item = usize>,
-) {
-    let stride = light_map.stride();
+/// How much light passes through a tile position, and how much it gives off, from both layers.
+/// Sky light (where both layers are empty) comes on top of this.
+pub fn tile_light(fg_tile: Tile, bg_tile: Tile) -> (u8, [u8; 3]) {
+    let fade = match (fg_tile, bg_tile) {
+        (Tile::NONE, Tile::NONE) => MIN_FADE,
+        (Tile::NONE, _) => TRANSPARENT_FADE,
+        (_, _) => fg_tile.properties().fade,
+    };
+    let (fr, fg, fb) = fg_tile.properties().light;
+    let (br, bg, bb) = bg_tile.properties().light;
+    (fade, [fr.max(br), fg.max(bg), fb.max(bb)])
+}
 
-    let mut queue = std::collections::VecDeque::from_iter(lights);
+// Left, right, top and bottom of ``index``, wrapping around the edges.
+#[inline(always)]
+fn neighbours(w: usize, h: usize, index: usize) -> [usize; 4] {
+    let row = index & !(w - 1);
+    let x = index & (w - 1);
+    let size = w * h;
+    [
+        row | (x.wrapping_sub(1) & (w - 1)),
+        row | ((x + 1) & (w - 1)),
+        (index + size - w) & (size - 1),
+        (index + w) & (size - 1),
+    ]
+}
 
+// Spreads light out from ``queue``, losing each tile's fade per step.
+#[inline(always)]
+fn flood(light_map: &mut FastArray2D<u8>, fade_map: &FastArray2D<u8>, mut queue: VecDeque<usize>) {
+    let (w, h) = light_map.size();
     while let Some(index) = queue.pop_front() {
-        let brightness = light_map[index];
-        let fade = fade_map[index];
-        let new_brightness = brightness.saturating_sub(fade);
-
-        // Left
-        let next_index = index - 1;
-        if light_map[next_index] < new_brightness {
-            light_map[next_index] = new_brightness;
-            queue.push_back(next_index);
+        let new_brightness = light_map[index].saturating_sub(fade_map[index]);
+        for next_index in neighbours(w, h, index) {
+            if light_map[next_index] < new_brightness {
+                light_map[next_index] = new_brightness;
+                queue.push_back(next_index);
+            }
         }
+    }
+}
 
-        // Right
-        let next_index = index + 1;
-        if light_map[next_index] < new_brightness {
-            light_map[next_index] = new_brightness;
-            queue.push_back(next_index);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::registry::*;
 
-        // Top
-        let next_index = index - stride;
-        if light_map[next_index] < new_brightness {
-            light_map[next_index] = new_brightness;
-            queue.push_back(next_index);
+    // xorshift64, mapped to [0, 1).
+    fn rng(mut state: u64) -> impl FnMut() -> f32 {
+        move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32
         }
+    }
+
+    fn random_light(rand: &mut impl FnMut() -> f32) -> (u8, u8, u8) {
+        let max = (MAX_BRIGHTNESS + 1) as f32;
+        (
+            (rand() * max) as u8,
+            (rand() * max) as u8,
+            (rand() * max) as u8,
+        )
+    }
+
+    #[test]
+    fn incremental_matches_recompute() {
+        let kinds = ["stone", "torch", "dirt"].map(|name| REGISTRY.tile(name).unwrap());
+        let mut fg = FastArray2D::from_value(5, 5, Tile::NONE);
+        let mut bg = FastArray2D::from_value(5, 5, Tile::NONE);
+        let mut light = LightEngine::new(5, 5);
+        let mut rand = rng(12345);
+        let mut emitters: Vec<(usize, usize, (u8, u8, u8))> = vec![];
 
-        // Bottom
-        let next_index = index + stride;
-        if light_map[next_index] < new_brightness {
-            light_map[next_index] = new_brightness;
-            queue.push_back(next_index);
+        for step in 0..1000 {
+            // Edit a tile, sometimes in bulk (like a chunk arriving).
+            let (x, y) = ((rand() * 32.) as usize, (rand() * 32.) as usize);
+            let (w, h) = match step % 100 {
+                0 => (8, 8),
+                _ => (1, 1),
+            };
+            let tile = match (rand() * 4.) as usize {
+                0 => Tile::NONE,
+                i => kinds[i - 1],
+            };
+            let layer = match rand() < 0.5 {
+                true => &mut fg,
+                false => &mut bg,
+            };
+            for_each_sub_wrapping(32, 32, x..x + w, y..y + h, |_, _, index| {
+                layer[index] = tile
+            });
+            light.update_tiles(&fg, &bg, x..x + w, y..y + h);
+            assert_eq!(light.count_mismatches(), 0, "step {step}, tile edit");
+
+            // Add, move or remove an emitter.
+            let position = ((rand() * 40.) as usize, (rand() * 40.) as usize);
+            let color = random_light(&mut rand);
+            match (rand() * 3.) as usize {
+                0 => emitters.push((position.0, position.1, color)),
+                1 if !emitters.is_empty() => {
+                    let i = (rand() * emitters.len() as f32) as usize;
+                    (emitters[i].0, emitters[i].1) = position;
+                }
+                _ if !emitters.is_empty() => {
+                    emitters.swap_remove((rand() * emitters.len() as f32) as usize);
+                }
+                _ => {}
+            }
+            light.set_emitters(emitters.iter().copied());
+            assert_eq!(light.count_mismatches(), 0, "step {step}, emitters");
+
+            // Change the sky.
+            if step % 10 == 0 {
+                light.set_sky(random_light(&mut rand));
+                assert_eq!(light.count_mismatches(), 0, "step {step}, sky");
+            }
         }
     }
 }
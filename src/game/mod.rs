@@ -1,6 +1,10 @@
 pub mod collision;
+pub mod delta;
 pub mod humanoid;
+pub mod inventory;
 pub mod item;
 pub mod lighting;
 pub mod net;
+pub mod registry;
+pub mod sky;
 pub mod tile;
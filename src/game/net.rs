@@ -1,36 +1,171 @@
+use crate::game::delta::*;
 use crate::game::humanoid::*;
-use crate::game::item::*;
+use crate::game::inventory::*;
+use crate::game::registry::*;
 use crate::game::tile::*;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+
+/// Bumped for each release that changes NetEvent (or anything it carries) incompatibly, and shown
+/// when a connection is refused. Builds in between are told apart by BUILD_HASH, the real check.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Hash of the files defining the protocol and the wire format, so builds with different net
+/// types or transports refuse each other even if PROTOCOL_VERSION wasn't bumped.
+pub const BUILD_HASH: u64 = {
+    let sources: [&str; 8] = [
+        env!("CARGO_PKG_VERSION"),
+        include_str!("net.rs"),
+        include_str!("../net.rs"),
+        include_str!("delta.rs"),
+        include_str!("humanoid.rs"),
+        include_str!("inventory.rs"),
+        include_str!("item.rs"),
+        include_str!("tile.rs"),
+    ];
+
+    let mut hash = 0xCBF2_9CE4_8422_2325u64;
+    let mut i = 0;
+    while i < sources.len() {
+        hash = fnv1a(hash, sources[i].as_bytes());
+        i += 1;
+    }
+    hash
+};
+
+/// Continues an FNV-1a hash over ``bytes``.
+pub const fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u64).wrapping_mul(0x0100_0000_01B3);
+        i += 1;
+    }
+    hash
+}
+
+/// BUILD_HASH combined with the registry's, since both ends need the same tiles and items too.
+pub fn content_hash() -> u64 {
+    fnv1a(BUILD_HASH, &REGISTRY.hash.to_le_bytes())
+}
+
+pub const MAX_NAME_LEN: usize = 32; // In bytes.
+
+/// Why the server turned a connection away.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    VersionMismatch(u32, u64), // server protocol version, server build hash
+    ServerFull,
+    Banned,
+    NameTaken,
+    InvalidName,
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RejectReason::VersionMismatch(version, build_hash) => write!(
+                f,
+                "version mismatch (server is protocol {version} build {build_hash:016x}, \
+                 client is protocol {PROTOCOL_VERSION} build {:016x})",
+                content_hash()
+            ),
+            RejectReason::ServerFull => write!(f, "the server is full"),
+            RejectReason::Banned => write!(f, "you are banned from this server"),
+            RejectReason::NameTaken => write!(f, "that name is already taken"),
+            RejectReason::InvalidName => {
+                write!(
+                    f,
+                    "names must be 1 to {MAX_NAME_LEN} bytes, without control characters"
+                )
+            }
+        }
+    }
+}
+
+/// Returns true if ``name`` can be used as a player name.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN && !name.chars().any(char::is_control)
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum NetEvent {
     // Connection
-    Connect,
-    Accept(u16, u16, u64), // world_w, world_h, player_x, player_y
+    Connect(u32, u64, u64, String), // protocol version, build hash, nonce, player name
+    Accept(u64, u16, u16, u64),     // nonce, world_w, world_h, player_id
+    Reject(u64, RejectReason),      // nonce, reason
     Ping,
     Disconnect,
     Close,
 
     // To server.
     RequestChunk(u16, u16),
-    UpdateHumanoid(u64, HumanoidPhysics),
-    BreakForeground(u16, u16),
-    BreakBackground(u16, u16),
+    ViewSize(u16, u16),              // The client's window (in pixels).
+    PlayerInput(Vec<HumanoidInput>), // The most recent inputs, oldest first.
+    AckSnapshot(u32),                // The newest snapshot decoded.
+    BreakForeground(u16, u16),       // Start mining a tile, progress resets if the target changes.
+    BreakBackground(u16, u16),       // Start mining a tile, progress resets if the target changes.
+    StopMining,                      // Mine nothing. From the server, the target was dropped.
+    PlaceForeground(u16, u16),       // From the selected slot.
+    PlaceBackground(u16, u16),       // From the selected slot.
+    SelectSlot(u8),                  // A hotbar slot.
+    InventoryAction(InventoryAction),
 
     // To client.
-    HumanoidData(BTreeMap<u64, HumanoidPhysics>),
-    ItemData(BTreeMap<u64, Item>),
+    Snapshot(Box<SnapshotDelta>),
     UpdateForegroundTile(u16, u16, Tile),
     UpdateBackgroundTile(u16, u16, Tile),
-    UpdateForegroundChunk(u16, u16, Box<[Tile]>),
-    UpdateBackgroundChunk(u16, u16, Box<[Tile]>),
+    UpdateInventory(Inventory),
+    UpdateChunks(Vec<(u16, u16, CompressedChunk)>), // Requested chunks, nearest first.
+    WorldTime(u64),                                 // Time of day (in us since midnight).
 
     // Chat.
     ChatMessage(String),
 }
 
+/// How an event is delivered. See crate::net.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Unreliable,        // May be lost, duplicated or reordered.
+    ReliableUnordered, // Arrives exactly once, in any order.
+    ReliableOrdered,   // Arrives exactly once, in the order it was sent.
+}
+
+impl NetEvent {
+    pub fn delivery(&self) -> Delivery {
+        match self {
+            // Sent repeatedly, so losing one doesn't matter. A Reject is answered to every Connect.
+            NetEvent::Connect(..)
+            | NetEvent::Reject(..)
+            | NetEvent::Ping
+            | NetEvent::PlayerInput(..)
+            | NetEvent::AckSnapshot(..)
+            | NetEvent::Snapshot(..)
+            | NetEvent::WorldTime(..) => Delivery::Unreliable,
+
+            // Asking twice for a chunk does no harm, so requests can arrive in any order.
+            NetEvent::RequestChunk(..) => Delivery::ReliableUnordered,
+
+            // Tile edits must be applied in order, or a stale edit may win. That includes chunks,
+            // which would otherwise overwrite the edits made after they were sent.
+            NetEvent::Accept(..)
+            | NetEvent::Disconnect
+            | NetEvent::Close
+            | NetEvent::ViewSize(..)
+            | NetEvent::BreakForeground(..)
+            | NetEvent::BreakBackground(..)
+            | NetEvent::StopMining
+            | NetEvent::PlaceForeground(..)
+            | NetEvent::PlaceBackground(..)
+            | NetEvent::SelectSlot(..)
+            | NetEvent::InventoryAction(..)
+            | NetEvent::UpdateInventory(..)
+            | NetEvent::UpdateChunks(..)
+            | NetEvent::UpdateForegroundTile(..)
+            | NetEvent::UpdateBackgroundTile(..)
+            | NetEvent::ChatMessage(..) => Delivery::ReliableOrdered,
+        }
+    }
+}
+
 impl IntoIterator for NetEvent {
     type Item = Self;
     type IntoIter = std::iter::Once<Self>;
@@ -0,0 +1,318 @@
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::game::item::*;
+use crate::game::lighting::*;
+use crate::game::tile::*;
+
+lazy_static! {
+    /// Every tile and item, loaded from resources/registry.toml the first time it's needed.
+    pub static ref REGISTRY: Registry = {
+        let path = crate::io::get_root().join("resources").join("registry.toml");
+        Registry::from_file(&path).unwrap_or_else(|e| panic!("{e}"))
+    };
+}
+
+/// How a tile looks and behaves.
+#[derive(Clone, Debug)]
+pub struct TileProperties {
+    pub name: String,
+    pub uv: Option<(u16, u16)>, // Top left of its sprite in tile_sheet.png. None isn't drawn.
+    pub solid: bool,            // Whether humanoids and items collide with it.
+    pub fade: u8,               // Light lost passing through it (in the foreground).
+    pub light: (u8, u8, u8),    // Red, green and blue brightness it gives off.
+    pub hardness: u16,          // How long it takes to break.
+    pub drop: Option<ItemId>,   // What it leaves behind when broken.
+}
+
+/// How an item looks and behaves.
+#[derive(Clone, Debug)]
+pub struct ItemProperties {
+    pub name: String,
+    pub icon: (u16, u16), // Top left of its sprite in tile_sheet.png.
+    pub max_stack: u16,
+    pub places: Option<Tile>,
+    pub mining_power: Option<u16>, // Hardness mined per second, for tools.
+    pub light: (u8, u8, u8),       // Red, green and blue brightness it gives off, dropped or held.
+}
+
+pub struct Registry {
+    pub tiles: Vec<TileProperties>, // Indexed by tile id.
+    pub items: Vec<ItemProperties>, // Indexed by item id.
+    pub hash: u64,                  // Of the file it was loaded from.
+    tile_names: HashMap<String, Tile>,
+    item_names: HashMap<String, ItemId>,
+}
+
+// The file as written. Tiles and items refer to each other by name.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    tile: Vec<TileDef>,
+    item: Vec<ItemDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TileDef {
+    id: u8,
+    name: String,
+    uv: Option<(u16, u16)>,
+    solid: bool,
+    fade: u8,
+    #[serde(default)]
+    light: (u8, u8, u8),
+    #[serde(default)]
+    hardness: u16,
+    drop: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ItemDef {
+    id: u16,
+    name: String,
+    icon: (u16, u16),
+    max_stack: u16,
+    places: Option<String>,
+    mining_power: Option<u16>,
+    #[serde(default)]
+    light: (u8, u8, u8),
+}
+
+impl Registry {
+    pub fn from_file(path: &std::path::Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read registry {path:?}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("invalid registry {path:?}: {e}"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: RegistryFile = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        // Ids end up in saves and on the wire, so they're written out rather than implied by the
+        // order, but they still have to count up from 0.
+        for (i, def) in file.tile.iter().enumerate() {
+            if def.id as usize != i {
+                return Err(format!(
+                    "tile {:?} has id {}, expected {i}",
+                    def.name, def.id
+                ));
+            }
+        }
+        for (i, def) in file.item.iter().enumerate() {
+            if def.id as usize != i {
+                return Err(format!(
+                    "item {:?} has id {}, expected {i}",
+                    def.name, def.id
+                ));
+            }
+        }
+        if file.tile.first().map_or(true, |def| def.solid) {
+            return Err(String::from("tile 0 must be empty space (not solid)"));
+        }
+
+        // Names.
+        let mut tile_names = HashMap::new();
+        for def in &file.tile {
+            if tile_names.insert(def.name.clone(), Tile(def.id)).is_some() {
+                return Err(format!("tile {:?} is defined twice", def.name));
+            }
+        }
+        let mut item_names = HashMap::new();
+        for def in &file.item {
+            if item_names
+                .insert(def.name.clone(), ItemId(def.id))
+                .is_some()
+            {
+                return Err(format!("item {:?} is defined twice", def.name));
+            }
+        }
+
+        let mut tiles = Vec::with_capacity(file.tile.len());
+        for def in file.tile {
+            if !(MIN_FADE..=MAX_FADE).contains(&def.fade) || !valid_light(def.light) {
+                return Err(format!(
+                    "tile {:?} has fade or light out of range",
+                    def.name
+                ));
+            }
+            let drop = match def.drop {
+                Some(name) => match item_names.get(&name) {
+                    Some(&id) => Some(id),
+                    None => return Err(format!("unknown item {name:?} dropped by {:?}", def.name)),
+                },
+                None => None,
+            };
+            tiles.push(TileProperties {
+                name: def.name,
+                uv: def.uv,
+                solid: def.solid,
+                fade: def.fade,
+                light: def.light,
+                hardness: def.hardness,
+                drop,
+            });
+        }
+
+        let mut items = Vec::with_capacity(file.item.len());
+        for def in file.item {
+            if def.max_stack == 0 || def.mining_power == Some(0) {
+                return Err(format!(
+                    "item {:?} has max_stack or mining_power 0",
+                    def.name
+                ));
+            }
+            if !valid_light(def.light) {
+                return Err(format!("item {:?} has light out of range", def.name));
+            }
+            let places = match def.places {
+                Some(name) => match tile_names.get(&name) {
+                    Some(&tile) if tile != Tile::NONE => Some(tile),
+                    _ => return Err(format!("unknown tile {name:?} placed by {:?}", def.name)),
+                },
+                None => None,
+            };
+            items.push(ItemProperties {
+                name: def.name,
+                icon: def.icon,
+                max_stack: def.max_stack,
+                places,
+                mining_power: def.mining_power,
+                light: def.light,
+            });
+        }
+
+        Ok(Self {
+            tiles,
+            items,
+            hash: crate::game::net::fnv1a(0xCBF2_9CE4_8422_2325, text.as_bytes()),
+            tile_names,
+            item_names,
+        })
+    }
+
+    pub fn tile(&self, name: &str) -> Option<Tile> {
+        self.tile_names.get(name).copied()
+    }
+
+    pub fn item(&self, name: &str) -> Option<ItemId> {
+        self.item_names.get(name).copied()
+    }
+}
+
+fn valid_light((r, g, b): (u8, u8, u8)) -> bool {
+    r.max(g).max(b) <= MAX_BRIGHTNESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small valid registry, with ``extra`` appended.
+    fn parse_with(extra: &str) -> Result<Registry, String> {
+        let base = r#"
+            [[tile]]
+            id = 0
+            name = "none"
+            solid = false
+            fade = 1
+
+            [[tile]]
+            id = 1
+            name = "dirt"
+            uv = [16, 0]
+            solid = true
+            fade = 3
+            drop = "dirt"
+
+            [[item]]
+            id = 0
+            name = "dirt"
+            icon = [16, 0]
+            max_stack = 99
+            places = "dirt"
+        "#;
+        Registry::parse(&format!("{base}\n{extra}"))
+    }
+
+    #[test]
+    fn parses_and_links_names() {
+        let registry = parse_with("").unwrap();
+        let dirt_tile = registry.tile("dirt").unwrap();
+        let dirt_item = registry.item("dirt").unwrap();
+        assert_eq!(registry.tiles[dirt_tile.0 as usize].drop, Some(dirt_item));
+        assert_eq!(registry.items[dirt_item.0 as usize].places, Some(dirt_tile));
+        assert_eq!(registry.tile("stone"), None);
+    }
+
+    #[test]
+    fn the_real_registry_parses() {
+        let path = crate::io::get_root()
+            .join("resources")
+            .join("registry.toml");
+        Registry::from_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_id_gaps() {
+        let gap = "[[tile]]\nid = 3\nname = \"stone\"\nsolid = true\nfade = 4";
+        assert!(parse_with(gap).err().unwrap().contains("expected 2"));
+        let gap = "[[item]]\nid = 2\nname = \"torch\"\nicon = [0, 0]\nmax_stack = 99";
+        assert!(parse_with(gap).err().unwrap().contains("expected 1"));
+    }
+
+    #[test]
+    fn rejects_solid_tile_0() {
+        let text =
+            "[[tile]]\nid = 0\nname = \"none\"\nsolid = true\nfade = 1\n\n[[item]]\nid = 0\n\
+                    name = \"dirt\"\nicon = [0, 0]\nmax_stack = 1";
+        assert!(Registry::parse(text).err().unwrap().contains("tile 0"));
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let dup = "[[tile]]\nid = 2\nname = \"dirt\"\nsolid = true\nfade = 3";
+        assert!(parse_with(dup).err().unwrap().contains("twice"));
+        let dup = "[[item]]\nid = 1\nname = \"dirt\"\nicon = [0, 0]\nmax_stack = 99";
+        assert!(parse_with(dup).err().unwrap().contains("twice"));
+    }
+
+    #[test]
+    fn rejects_unknown_references() {
+        let drop = "[[tile]]\nid = 2\nname = \"stone\"\nsolid = true\nfade = 4\ndrop = \"rock\"";
+        assert!(parse_with(drop).err().unwrap().contains("unknown item"));
+        let places = "[[item]]\nid = 1\nname = \"torch\"\nicon = [0, 0]\nmax_stack = 99\n\
+                      places = \"torch\"";
+        assert!(parse_with(places).err().unwrap().contains("unknown tile"));
+        let places = "[[item]]\nid = 1\nname = \"air\"\nicon = [0, 0]\nmax_stack = 99\n\
+                      places = \"none\"";
+        assert!(parse_with(places).err().unwrap().contains("unknown tile"));
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let tile = |fade: u8, light: u8| {
+            format!(
+                "[[tile]]\nid = 2\nname = \"lamp\"\nsolid = true\nfade = {fade}\n\
+                 light = [{light}, 0, 0]"
+            )
+        };
+        assert!(parse_with(&tile(MIN_FADE, MAX_BRIGHTNESS)).is_ok());
+        assert!(parse_with(&tile(0, 0)).is_err());
+        assert!(parse_with(&tile(MAX_FADE + 1, 0)).is_err());
+        assert!(parse_with(&tile(1, MAX_BRIGHTNESS + 1)).is_err());
+
+        let item = |max_stack: u16, power: u16, light: u8| {
+            format!(
+                "[[item]]\nid = 1\nname = \"pick\"\nicon = [0, 0]\nmax_stack = {max_stack}\n\
+                 mining_power = {power}\nlight = [0, {light}, 0]"
+            )
+        };
+        assert!(parse_with(&item(1, 8, MAX_BRIGHTNESS)).is_ok());
+        assert!(parse_with(&item(0, 8, 0)).is_err());
+        assert!(parse_with(&item(1, 0, 0)).is_err());
+        assert!(parse_with(&item(1, 8, MAX_BRIGHTNESS + 1)).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::game::lighting::*;
+
+// The world clock. Times are in microseconds since midnight.
+pub const DAY_LENGTH_US: u64 = 20 * 60 * 1_000_000; // One day is 20 real minutes.
+pub const HOUR_US: u64 = DAY_LENGTH_US / 24;
+pub const NEW_WORLD_TIME_US: u64 = 8 * HOUR_US; // New worlds start in the morning.
+const SUNRISE_US: (u64, u64) = (5 * HOUR_US, 7 * HOUR_US); // From night to full day.
+const SUNSET_US: (u64, u64) = (17 * HOUR_US, 19 * HOUR_US); // From full day to night.
+const SKY_LIGHT_STEP_US: u64 = HOUR_US / 3; // Sky light only changes this often.
+
+// Sky light and clear color at noon, at midnight, and halfway through sunrise or sunset.
+const DAY_LIGHT: [u8; 3] = [MAX_BRIGHTNESS; 3];
+const NIGHT_LIGHT: [u8; 3] = [2, 3, 6]; // Moonlight, just enough to see the surface.
+const DAY_COLOR: [f32; 3] = [0x15 as f32 / 256., 0x9F as f32 / 256., 0xEA as f32 / 256.];
+const NIGHT_COLOR: [f32; 3] = [0x04 as f32 / 256., 0x06 as f32 / 256., 0x18 as f32 / 256.];
+const DUSK_COLOR: [f32; 3] = [0xE0 as f32 / 256., 0x70 as f32 / 256., 0x48 as f32 / 256.];
+
+/// How much of the sun is out at ``time_us``, from 0 (night) to 1 (day).
+pub fn daylight(time_us: u64) -> f32 {
+    let t = time_us % DAY_LENGTH_US;
+    let ramp = |(a, b): (u64, u64)| (t.saturating_sub(a) as f32 / (b - a) as f32).min(1.);
+    ramp(SUNRISE_US) - ramp(SUNSET_US)
+}
+
+/// The red, green and blue brightness of open sky at ``time_us``. Changes in steps, since every
+/// change relights all the open sky around the player.
+pub fn sky_light(time_us: u64) -> (u8, u8, u8) {
+    let d = daylight(time_us - time_us % SKY_LIGHT_STEP_US);
+    let [r, g, b] = [0, 1, 2].map(|c| {
+        let (night, day) = (NIGHT_LIGHT[c] as f32, DAY_LIGHT[c] as f32);
+        (night + (day - night) * d).round() as u8
+    });
+    (r, g, b)
+}
+
+/// The color behind everything at ``time_us``, reddest halfway through sunrise and sunset.
+pub fn sky_color(time_us: u64) -> (f32, f32, f32) {
+    let d = daylight(time_us);
+    let dusk = 1. - (2. * d - 1.).abs();
+    let [r, g, b] = [0, 1, 2].map(|c| {
+        let color = NIGHT_COLOR[c] + (DAY_COLOR[c] - NIGHT_COLOR[c]) * d;
+        color + (DUSK_COLOR[c] - color) * 0.5 * dusk
+    });
+    (r, g, b)
+}
+
+/// Reads a time of day like "06:30", "dawn", "noon", "dusk" or "midnight".
+pub fn parse_time_of_day(s: &str) -> Result<u64, String> {
+    let time = match s {
+        "dawn" => 6 * HOUR_US,
+        "noon" | "day" => 12 * HOUR_US,
+        "dusk" => 18 * HOUR_US,
+        "midnight" | "night" => 0,
+        _ => {
+            let parsed = s.split_once(':').and_then(|(h, m)| {
+                let (h, m) = (h.parse::<u64>().ok()?, m.parse::<u64>().ok()?);
+                // Rounded up, so it reads back as the same minute.
+                (h < 24 && m < 60).then(|| ((h * 60 + m) * DAY_LENGTH_US).div_ceil(24 * 60))
+            });
+            parsed
+                .ok_or_else(|| format!("expected HH:MM, dawn, noon, dusk or midnight, not {s:?}"))?
+        }
+    };
+    Ok(time)
+}
+
+/// Writes a time of day as "HH:MM".
+pub fn format_time_of_day(time_us: u64) -> String {
+    let minutes = time_us % DAY_LENGTH_US * 24 * 60 / DAY_LENGTH_US;
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_of_day_round_trips() {
+        for text in ["00:00", "06:30", "12:00", "23:59"] {
+            assert_eq!(format_time_of_day(parse_time_of_day(text).unwrap()), text);
+        }
+        assert_eq!(parse_time_of_day("noon"), Ok(12 * HOUR_US));
+        assert_eq!(parse_time_of_day("midnight"), Ok(0));
+        assert_eq!(format_time_of_day(DAY_LENGTH_US + HOUR_US), "01:00");
+    }
+
+    #[test]
+    fn bad_times_of_day_are_rejected() {
+        for text in ["", "12", "24:00", "12:60", "-1:00", "12:xx", "teatime"] {
+            assert!(parse_time_of_day(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn sky_light_follows_the_sun() {
+        let (noon, midnight) = (12 * HOUR_US, 0);
+        assert_eq!(
+            sky_light(noon),
+            (MAX_BRIGHTNESS, MAX_BRIGHTNESS, MAX_BRIGHTNESS)
+        );
+        let [r, g, b] = NIGHT_LIGHT;
+        assert_eq!(sky_light(midnight), (r, g, b));
+        assert_eq!(sky_light(midnight + DAY_LENGTH_US), sky_light(midnight));
+
+        // Brightens through sunrise and darkens through sunset, a few steps at a time.
+        let sunrise: Vec<_> = (SUNRISE_US.0..=SUNRISE_US.1).step_by(1_000_000).collect();
+        let sunset: Vec<_> = (SUNSET_US.0..=SUNSET_US.1).step_by(1_000_000).collect();
+        for (times, brighter) in [(sunrise, true), (sunset, false)] {
+            let levels: Vec<_> = times.into_iter().map(sky_light).collect();
+            let changes = levels.windows(2).filter(|w| w[0] != w[1]).count();
+            assert!(changes > 0 && changes <= (2 * HOUR_US / SKY_LIGHT_STEP_US) as usize);
+            for w in levels.windows(2) {
+                let (a, b) = match brighter {
+                    true => (w[0], w[1]),
+                    false => (w[1], w[0]),
+                };
+                assert!(a.0 <= b.0 && a.1 <= b.1 && a.2 <= b.2);
+            }
+        }
+    }
+}
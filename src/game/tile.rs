@@ -1,4 +1,5 @@
 use crate::game::lighting::*;
+use crate::game::registry::*;
 use serde::{Deserialize, Serialize};
 
 // Tile:
@@ -9,10 +10,81 @@ pub const CHUNK_SIZE: usize = 8; // Chunk size (in tiles) (must be power of two)
 pub const CHUNK_SIZE_LOG2: usize = 3; // Round will probably break this sometimes (?)
 pub const CHUNK_LOAD_BUFFER_SIZE: usize = MAX_LIGHT_DISTANCE as usize; // (in tiles).
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
-#[repr(u8)]
-pub enum Tile {
-    None = 0,
-    Dirt = 1,
-    Stone = 2,
+/// A tile id. What each one is comes from the registry, except 0 which is always empty space.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Tile(pub u8);
+
+impl Tile {
+    pub const NONE: Tile = Tile(0);
+
+    pub fn properties(self) -> &'static TileProperties {
+        &REGISTRY.tiles[self.0 as usize]
+    }
+
+    pub fn is_solid(self) -> bool {
+        self.properties().solid
+    }
+}
+
+impl TryFrom<u8> for Tile {
+    type Error = String;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match (value as usize) < REGISTRY.tiles.len() {
+            true => Ok(Tile(value)),
+            false => Err(format!("unknown tile {value}")),
+        }
+    }
+}
+
+impl From<Tile> for u8 {
+    fn from(tile: Tile) -> u8 {
+        tile.0
+    }
+}
+
+/// A chunk's foreground and background tiles, run-length encoded. Chunks are mostly long runs of
+/// the same tile (air, stone, dirt), so this is usually a small fraction of their raw size.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompressedChunk {
+    runs: Vec<u8>, // (length, tile) pairs, foreground then background.
+}
+
+impl CompressedChunk {
+    pub fn new(foreground: &[Tile], background: &[Tile]) -> Self {
+        let mut runs: Vec<u8> = vec![];
+        for &tile in foreground.iter().chain(background) {
+            match runs.len() {
+                n if n > 0 && runs[n - 1] == tile.0 && runs[n - 2] < u8::MAX => runs[n - 2] += 1,
+                _ => runs.extend([1, tile.0]),
+            }
+        }
+        Self { runs }
+    }
+
+    /// Roughly how many bytes this takes on the wire.
+    pub fn size(&self) -> usize {
+        self.runs.len() + 8
+    }
+
+    /// Returns the foreground and background tiles, or None if the runs don't add up to a chunk.
+    pub fn decompress(&self) -> Option<(Box<[Tile]>, Box<[Tile]>)> {
+        const CHUNK_TILES: usize = CHUNK_SIZE * CHUNK_SIZE;
+        let mut tiles = Vec::with_capacity(2 * CHUNK_TILES);
+        for run in self.runs.chunks(2) {
+            let &[length, tile] = run else {
+                return None;
+            };
+            let tile = Tile::try_from(tile).ok()?;
+            tiles.extend(std::iter::repeat(tile).take(length as usize));
+            if tiles.len() > 2 * CHUNK_TILES {
+                return None;
+            }
+        }
+        if tiles.len() != 2 * CHUNK_TILES {
+            return None;
+        }
+        let background = tiles.split_off(CHUNK_TILES);
+        Some((tiles.into_boxed_slice(), background.into_boxed_slice()))
+    }
 }
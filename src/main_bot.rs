@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+mod array2d;
+mod client;
+mod common;
+mod game;
+mod io;
+mod net;
+mod time;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+fn main() {
+    // Load config.
+    use client::bot::*;
+    let config = match BotConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit((e != BOT_USAGE) as i32);
+        }
+    };
+
+    // Spawn bots, each on its own thread.
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut bots = vec![];
+    for i in 0..config.bots {
+        let mut client = config.client.clone();
+        client.player_name = format!("{}{}", config.client.player_name, i + 1);
+        let input = match &config.script {
+            Some(script) => BotInput::script(script.clone()),
+            None => BotInput::random(
+                config.seed ^ (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15),
+                client.bindings.clone(),
+            ),
+        };
+        let stats = Arc::new(Mutex::new(BotStats::default()));
+        let (bot_stats, bot_stop) = (stats.clone(), stop.clone());
+        let handle = std::thread::Builder::new()
+            .name(format!("bot_{}", i + 1))
+            .spawn(move || run_bot(client, input, bot_stats, bot_stop))
+            .unwrap();
+        bots.push((handle, stats));
+    }
+    println!("[Bot] Started {} bots.", bots.len());
+
+    // Report every so often, until time is up or every bot has stopped.
+    let snapshot = || -> Vec<BotStats> {
+        bots.iter()
+            .map(|(_, s)| s.lock().unwrap().clone())
+            .collect()
+    };
+    let start_us = time::get_microseconds_as_u64();
+    let (mut last, mut last_us) = (snapshot(), start_us);
+    let first = last.clone();
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let now_us = time::get_microseconds_as_u64();
+        let done = bots.iter().all(|(handle, _)| handle.is_finished());
+        let timed_out = config
+            .duration_s
+            .map_or(false, |s| now_us - start_us >= s * 1_000_000);
+        if now_us - last_us >= config.report_s * 1_000_000 || done || timed_out {
+            let current = snapshot();
+            println!("[Bot] {}", report(&last, &current, now_us - last_us));
+            (last, last_us) = (current, now_us);
+        }
+        if done || timed_out {
+            break;
+        }
+    }
+
+    // Disconnect everyone, then sum up the whole run.
+    stop.store(true, Ordering::Relaxed);
+    let now_us = time::get_microseconds_as_u64();
+    let current = snapshot();
+    for (handle, _) in bots.drain(..) {
+        handle.join().unwrap();
+    }
+    println!(
+        "[Bot] Overall: {}",
+        report(&first, &current, now_us - start_us)
+    );
+}
@@ -9,28 +9,49 @@ mod net;
 mod time;
 
 use glutin::{
-    dpi::LogicalSize, event_loop::EventLoop, window::WindowBuilder, Api, ContextBuilder, GlRequest,
+    dpi::LogicalSize,
+    event_loop::EventLoop,
+    window::{Fullscreen, WindowBuilder},
+    Api, ContextBuilder, GlRequest,
 };
 
 fn main() {
+    // Load config.
+    use client::config::*;
+    let config = match ClientConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit((e != CLIENT_USAGE) as i32);
+        }
+    };
+
     // Build window and event loop.
     let event_loop = EventLoop::new();
     let windowed_context = ContextBuilder::new()
         .with_gl(GlRequest::Specific(Api::OpenGl, (4, 1)))
-        .with_vsync(true)
+        .with_vsync(config.vsync)
         .build_windowed(
             WindowBuilder::new()
                 .with_title("Trar")
-                .with_inner_size(LogicalSize::new(1080f64, 720f64)),
+                .with_inner_size(LogicalSize::new(
+                    config.window_w as f64,
+                    config.window_h as f64,
+                ))
+                .with_fullscreen(config.fullscreen.then(|| Fullscreen::Borderless(None))),
             &event_loop,
         )
-        .unwrap();
+        .unwrap_or_else(|e| {
+            eprintln!("Could not create an OpenGL 4.1 window: {e}");
+            std::process::exit(1);
+        });
 
     // Create communication channels.
     let (input_send, input_recv) = crossbeam_channel::bounded(100);
 
     // Spawn client.
-    let (update_handle, render_handle) = client::launch_client(windowed_context, input_recv);
+    let (update_handle, render_handle) =
+        client::launch_client(windowed_context, input_recv, config);
     std::mem::forget(update_handle);
     std::mem::forget(render_handle);
 
@@ -41,23 +62,24 @@ fn main() {
         use glutin::event::*;
         use glutin::event_loop::*;
 
+        // Sends fail once the update thread has quit, just wait for the window to close then.
+        let send = |event| input_send.send(event).unwrap_or(());
+
         *out = ControlFlow::Wait;
         match event {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::Destroyed => *out = ControlFlow::Exit,
-                WindowEvent::CloseRequested => input_send.send(InputEvent::Close).unwrap(),
-                WindowEvent::Resized(PhysicalSize { width, height }) => input_send
-                    .send(InputEvent::WindowResize(width as u16, height as u16))
-                    .unwrap(),
-                WindowEvent::Focused(state) => input_send.send(InputEvent::Focused(state)).unwrap(),
+                WindowEvent::CloseRequested => send(InputEvent::Close),
+                WindowEvent::Resized(PhysicalSize { width, height }) => {
+                    send(InputEvent::WindowResize(width as u16, height as u16))
+                }
+                WindowEvent::Focused(state) => send(InputEvent::Focused(state)),
 
                 // Mouse input
                 WindowEvent::CursorMoved {
                     position: PhysicalPosition { x, y },
                     ..
-                } => input_send
-                    .send(InputEvent::CursorMove(x as f32, y as f32))
-                    .unwrap(),
+                } => send(InputEvent::CursorMove(x as f32, y as f32)),
                 WindowEvent::MouseInput { state, button, .. } => {
                     // Map button state.
                     let button_state = match state {
@@ -74,9 +96,14 @@ fn main() {
                     };
 
                     // Send.
-                    input_send
-                        .send(InputEvent::KeyEvent(button_state, input_button))
-                        .unwrap();
+                    send(InputEvent::KeyEvent(button_state, input_button));
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => y as f32 / 16.,
+                    };
+                    send(InputEvent::Scroll(lines));
                 }
 
                 // Keyboard input.
@@ -97,17 +124,85 @@ fn main() {
 
                     // Map key type.
                     let input_key = match key {
-                        VirtualKeyCode::W => InputKey::W,
                         VirtualKeyCode::A => InputKey::A,
-                        VirtualKeyCode::S => InputKey::S,
+                        VirtualKeyCode::B => InputKey::B,
+                        VirtualKeyCode::C => InputKey::C,
                         VirtualKeyCode::D => InputKey::D,
+                        VirtualKeyCode::E => InputKey::E,
+                        VirtualKeyCode::F => InputKey::F,
+                        VirtualKeyCode::G => InputKey::G,
+                        VirtualKeyCode::H => InputKey::H,
+                        VirtualKeyCode::I => InputKey::I,
+                        VirtualKeyCode::J => InputKey::J,
+                        VirtualKeyCode::K => InputKey::K,
+                        VirtualKeyCode::L => InputKey::L,
+                        VirtualKeyCode::M => InputKey::M,
+                        VirtualKeyCode::N => InputKey::N,
+                        VirtualKeyCode::O => InputKey::O,
+                        VirtualKeyCode::P => InputKey::P,
+                        VirtualKeyCode::Q => InputKey::Q,
+                        VirtualKeyCode::R => InputKey::R,
+                        VirtualKeyCode::S => InputKey::S,
+                        VirtualKeyCode::T => InputKey::T,
+                        VirtualKeyCode::U => InputKey::U,
+                        VirtualKeyCode::V => InputKey::V,
+                        VirtualKeyCode::W => InputKey::W,
+                        VirtualKeyCode::X => InputKey::X,
+                        VirtualKeyCode::Y => InputKey::Y,
+                        VirtualKeyCode::Z => InputKey::Z,
+                        VirtualKeyCode::Key0 => InputKey::Key0,
+                        VirtualKeyCode::Key1 => InputKey::Key1,
+                        VirtualKeyCode::Key2 => InputKey::Key2,
+                        VirtualKeyCode::Key3 => InputKey::Key3,
+                        VirtualKeyCode::Key4 => InputKey::Key4,
+                        VirtualKeyCode::Key5 => InputKey::Key5,
+                        VirtualKeyCode::Key6 => InputKey::Key6,
+                        VirtualKeyCode::Key7 => InputKey::Key7,
+                        VirtualKeyCode::Key8 => InputKey::Key8,
+                        VirtualKeyCode::Key9 => InputKey::Key9,
+                        VirtualKeyCode::F1 => InputKey::F1,
+                        VirtualKeyCode::F2 => InputKey::F2,
+                        VirtualKeyCode::F3 => InputKey::F3,
+                        VirtualKeyCode::F4 => InputKey::F4,
+                        VirtualKeyCode::F5 => InputKey::F5,
+                        VirtualKeyCode::F6 => InputKey::F6,
+                        VirtualKeyCode::F7 => InputKey::F7,
+                        VirtualKeyCode::F8 => InputKey::F8,
+                        VirtualKeyCode::F9 => InputKey::F9,
+                        VirtualKeyCode::F10 => InputKey::F10,
+                        VirtualKeyCode::F11 => InputKey::F11,
+                        VirtualKeyCode::F12 => InputKey::F12,
+                        VirtualKeyCode::Left => InputKey::Left,
+                        VirtualKeyCode::Right => InputKey::Right,
+                        VirtualKeyCode::Up => InputKey::Up,
+                        VirtualKeyCode::Down => InputKey::Down,
+                        VirtualKeyCode::Space => InputKey::Space,
+                        VirtualKeyCode::Return => InputKey::Enter,
+                        VirtualKeyCode::Escape => InputKey::Escape,
+                        VirtualKeyCode::Tab => InputKey::Tab,
+                        VirtualKeyCode::Back => InputKey::Backspace,
+                        VirtualKeyCode::LShift => InputKey::LShift,
+                        VirtualKeyCode::RShift => InputKey::RShift,
+                        VirtualKeyCode::LControl => InputKey::LControl,
+                        VirtualKeyCode::RControl => InputKey::RControl,
+                        VirtualKeyCode::LAlt => InputKey::LAlt,
+                        VirtualKeyCode::RAlt => InputKey::RAlt,
+                        VirtualKeyCode::Grave => InputKey::Grave,
+                        VirtualKeyCode::Minus => InputKey::Minus,
+                        VirtualKeyCode::Equals => InputKey::Equals,
+                        VirtualKeyCode::LBracket => InputKey::LBracket,
+                        VirtualKeyCode::RBracket => InputKey::RBracket,
+                        VirtualKeyCode::Backslash => InputKey::Backslash,
+                        VirtualKeyCode::Semicolon => InputKey::Semicolon,
+                        VirtualKeyCode::Apostrophe => InputKey::Apostrophe,
+                        VirtualKeyCode::Comma => InputKey::Comma,
+                        VirtualKeyCode::Period => InputKey::Period,
+                        VirtualKeyCode::Slash => InputKey::Slash,
                         _ => return,
                     };
 
                     // Send.
-                    input_send
-                        .send(InputEvent::KeyEvent(key_state, input_key))
-                        .unwrap();
+                    send(InputEvent::KeyEvent(key_state, input_key));
                 }
                 _ => {}
             },
@@ -9,8 +9,25 @@ mod server;
 mod time;
 
 fn main() {
+    // Load config.
+    use server::config::*;
+    let config = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit((e != SERVER_USAGE) as i32);
+        }
+    };
+
     // Create a server
-    let (_server_port, server_handle) = server::launch_server(0xCAFE);
+    let (server_port, server_handle) = match server::launch_server(config) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    println!("[Server] Listening on port {server_port}.");
 
     // Wait on server
     server_handle.join().unwrap();
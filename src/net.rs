@@ -1,81 +1,954 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error, ErrorKind, Result};
 use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
 
 use crate::game::net::*;
+use crate::time::*;
 
 const PACKET_MAX_SIZE: usize = 576;
+const SENT_PACKET_HISTORY: usize = 256; // How many sent packets are remembered for acks.
+const RECEIVED_ID_HISTORY: usize = 1024; // How many reliable ids are remembered for deduping.
+const RELIABLE_WINDOW: u16 = RECEIVED_ID_HISTORY as u16 / 2; // Max span of ids in flight.
+const MIN_RESEND_US: u64 = 50_000;
+const MAX_RESEND_US: u64 = 1_000_000;
 
-pub fn send(socket: &UdpSocket, events: &[NetEvent]) -> usize {
-    let dst = socket.peer_addr().unwrap();
-    send_to(socket, dst, events)
+// Fragmentation.
+const FRAGMENT_SIZE: usize = 512; // Leaves room for the packet header and fragment fields.
+const MAX_MESSAGE_SIZE: usize = 256 * 1024; // Largest message that can be sent.
+const MAX_FRAGMENTS: usize = MAX_MESSAGE_SIZE / FRAGMENT_SIZE;
+const MAX_REASSEMBLIES: usize = 32; // Unreliable, per channel.
+const MAX_REASSEMBLY_BYTES: usize = 1024 * 1024; // Unreliable, per channel.
+const MAX_RELIABLE_REASSEMBLY_BYTES: usize = 8 * 1024 * 1024; // Reliable, per channel.
+const REASSEMBLY_TIMEOUT_US: u64 = 5_000_000; // Unreliable only.
+
+/// Something that can send and receive datagrams. Implemented by UdpSocket, and by LossySocket
+/// for running the net code in-process.
+pub trait Socket {
+    fn send_to(&self, buf: &[u8], dst: SocketAddr) -> Result<usize>;
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)>;
+}
+
+impl Socket for UdpSocket {
+    fn send_to(&self, buf: &[u8], dst: SocketAddr) -> Result<usize> {
+        // Connected sockets can't always use send_to.
+        match self.peer_addr() {
+            Ok(peer) if peer == dst => self.send(buf),
+            _ => UdpSocket::send_to(self, buf, dst),
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+}
+
+/// Per packet header. ``ack`` is the newest packet seen from the peer, and bit n of
+/// ``ack_bits`` is set if packet ``ack - 1 - n`` was seen too.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+struct PacketHeader {
+    seq: u16,
+    ack: u16,
+    ack_bits: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+enum Message {
+    Unreliable(NetEvent),
+    Reliable(u16, NetEvent),                  // id
+    Ordered(u16, u16, NetEvent),              // id, order
+    Fragment(u16, u16, u16, Vec<u8>),         // group, index, count, part of a serialized Message
+    ReliableFragment(u16, u16, u16, Vec<u8>), // id, index, count, part of a serialized Message
+}
+
+/// A fragmented message being put back together.
+struct Reassembly {
+    parts: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    reserved: usize, // Bytes counted against the budget, for the whole message.
+    started_us: u64,
+}
+
+struct PendingMessage {
+    id: u16,
+    bytes: Vec<u8>,
+    last_sent_us: Option<u64>,
+}
+
+struct SentPacket {
+    seq: u16,
+    sent_us: u64,
+    acked: bool,
+    pending_keys: Vec<u32>,
+}
+
+/// Returns true if sequence number ``a`` comes after ``b``, accounting for wrapping.
+#[inline(always)]
+fn seq_greater(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+/// Reliability state for one peer. Events are queued, then flushed into packets, each carrying
+/// acks for the packets received from the peer. Reliable events are resent until a packet that
+/// carried them is acked.
+pub struct Channel {
+    // Outgoing.
+    next_seq: u16,
+    next_reliable_id: u16,
+    next_order: u16,
+    next_pending_key: u32,
+    next_fragment_group: u16,
+    unreliable: Vec<Vec<u8>>,
+    pending: HashMap<u32, PendingMessage>,
+    pending_order: VecDeque<u32>, // Pending keys, oldest first.
+    sent_packets: Vec<Option<SentPacket>>,
+
+    // Incoming.
+    remote_seq: Option<u16>,
+    remote_ack_bits: u32,
+    ack_pending: bool,
+    received_ids: Vec<Option<u16>>,
+    next_recv_order: u16,
+    ordered_buffer: HashMap<u16, NetEvent>,
+    reassemblies: HashMap<u16, Reassembly>,
+    reliable_reassemblies: HashMap<u16, Reassembly>, // By message id.
+    last_recv_us: u64,
+
+    // Stats.
+    srtt_us: Option<u64>,
+    resent: u64,
 }
 
-pub fn send_to(socket: &UdpSocket, dst: SocketAddr, events: &[NetEvent]) -> usize {
-    let mut packet = [0u8; PACKET_MAX_SIZE];
-    let mut packet_size = 0;
-    let mut sent = 0;
+impl Channel {
+    pub fn new() -> Self {
+        Self {
+            next_seq: 0,
+            next_reliable_id: 0,
+            next_order: 0,
+            next_pending_key: 0,
+            next_fragment_group: 0,
+            unreliable: Vec::new(),
+            pending: HashMap::new(),
+            pending_order: VecDeque::new(),
+            sent_packets: (0..SENT_PACKET_HISTORY).map(|_| None).collect(),
+
+            remote_seq: None,
+            remote_ack_bits: 0,
+            ack_pending: false,
+            received_ids: vec![None; RECEIVED_ID_HISTORY],
+            next_recv_order: 0,
+            ordered_buffer: HashMap::new(),
+            reassemblies: HashMap::new(),
+            reliable_reassemblies: HashMap::new(),
+            last_recv_us: get_microseconds_as_u64(),
+
+            srtt_us: None,
+            resent: 0,
+        }
+    }
+
+    /// Queues an event, to be sent according to its delivery class on the next flush. Fails,
+    /// queueing nothing, if the event is too large to send.
+    pub fn queue(&mut self, event: NetEvent) -> Result<()> {
+        let (id, order) = (self.next_reliable_id, self.next_order);
+        let delivery = event.delivery();
+        let message = match delivery {
+            Delivery::Unreliable => Message::Unreliable(event),
+            Delivery::ReliableUnordered => Message::Reliable(id, event),
+            Delivery::ReliableOrdered => Message::Ordered(id, order, event),
+        };
+        let bytes = bincode::serialize(&message).unwrap();
+
+        // Checked before the id and order are used up, or the peer would wait for them forever.
+        if bytes.len() > MAX_MESSAGE_SIZE {
+            let error = format!("{} byte message is too large to send", bytes.len());
+            return Err(Error::new(ErrorKind::InvalidInput, error));
+        }
+        match delivery {
+            Delivery::Unreliable => {
+                self.queue_bytes(bytes, None);
+                return Ok(());
+            }
+            Delivery::ReliableUnordered => {}
+            Delivery::ReliableOrdered => self.next_order = order.wrapping_add(1),
+        }
+        self.next_reliable_id = id.wrapping_add(1);
+        self.queue_bytes(bytes, Some(id));
+        Ok(())
+    }
+
+    /// Queues a serialized message (reliable if given its id), splitting it into fragments if it
+    /// can't fit in one packet.
+    fn queue_bytes(&mut self, bytes: Vec<u8>, reliable_id: Option<u16>) {
+        let push = |channel: &mut Self, bytes: Vec<u8>| match reliable_id {
+            Some(id) => {
+                let key = channel.next_pending_key;
+                channel.next_pending_key = channel.next_pending_key.wrapping_add(1);
+                let last_sent_us = None;
+                channel.pending.insert(
+                    key,
+                    PendingMessage {
+                        id,
+                        bytes,
+                        last_sent_us,
+                    },
+                );
+                channel.pending_order.push_back(key);
+            }
+            None => channel.unreliable.push(bytes),
+        };
+
+        if bytes.len() <= FRAGMENT_SIZE {
+            push(self, bytes);
+            return;
+        }
+
+        // Each fragment is sent (and for reliable messages, acked) on its own. Reliable fragments
+        // are grouped by message id, so the peer can tell when they're duplicates.
+        let group = reliable_id.unwrap_or(self.next_fragment_group);
+        if reliable_id.is_none() {
+            self.next_fragment_group = self.next_fragment_group.wrapping_add(1);
+        }
+        let count = bytes.chunks(FRAGMENT_SIZE).len() as u16;
+        for (index, part) in bytes.chunks(FRAGMENT_SIZE).enumerate() {
+            let (index, part) = (index as u16, part.to_vec());
+            let fragment = match reliable_id {
+                Some(_) => Message::ReliableFragment(group, index, count, part),
+                None => Message::Fragment(group, index, count, part),
+            };
+            push(self, bincode::serialize(&fragment).unwrap());
+        }
+    }
+
+    /// Packs everything due to be sent into packets, and passes each one to ``send``. Returns the
+    /// number of bytes sent.
+    pub fn flush(&mut self, now_us: u64, mut send: impl FnMut(&[u8])) -> usize {
+        let resend_us = self.resend_us();
+        let mut packet = Vec::with_capacity(PACKET_MAX_SIZE);
+        let mut pending_keys = vec![];
+        let mut sent = 0;
+
+        // Reliable messages due for a (re)send, oldest first, then unreliable ones. Messages too
+        // far past the oldest unacked one wait, so the peer's dedupe history can't wrap.
+        self.pending_order
+            .retain(|key| self.pending.contains_key(key));
+        let oldest_id = self
+            .pending_order
+            .front()
+            .map_or(0, |key| self.pending[key].id);
+        let due = self.pending_order.iter().filter_map(|key| {
+            let message = &self.pending[key];
+            if message.id.wrapping_sub(oldest_id) >= RELIABLE_WINDOW {
+                return None;
+            }
+            match message.last_sent_us {
+                Some(t) if now_us.saturating_sub(t) < resend_us => None,
+                _ => Some((Some(*key), message.bytes.as_slice())),
+            }
+        });
+        let unreliable = self.unreliable.iter().map(|bytes| (None, bytes.as_slice()));
+        let mut messages: Vec<(Option<u32>, &[u8])> = due.chain(unreliable).collect();
+
+        // Always send something if the peer is waiting on acks.
+        if messages.is_empty() && self.ack_pending {
+            messages.push((None, &[]));
+        }
+
+        let header_size = bincode::serialized_size(&self.header()).unwrap() as usize;
+        debug_assert!(header_size + FRAGMENT_SIZE + 32 <= PACKET_MAX_SIZE);
+        let mut sent_keys = vec![];
+        let mut messages = messages.into_iter().peekable();
+        while let Some((key, bytes)) = messages.next() {
+            if packet.is_empty() {
+                packet.extend(bincode::serialize(&self.header()).unwrap());
+            }
+
+            packet.extend_from_slice(bytes);
+            pending_keys.extend(key);
+
+            // Send the packet if the next message won't fit.
+            let full = match messages.peek() {
+                Some((_, next)) => packet.len() + next.len() > PACKET_MAX_SIZE,
+                None => true,
+            };
+            if full {
+                send(&packet);
+                sent += packet.len();
+                sent_keys.extend_from_slice(&pending_keys);
+                let seq = self.next_seq;
+                self.next_seq = self.next_seq.wrapping_add(1);
+                self.sent_packets[seq as usize % SENT_PACKET_HISTORY] = Some(SentPacket {
+                    seq,
+                    sent_us: now_us,
+                    acked: false,
+                    pending_keys: std::mem::take(&mut pending_keys),
+                });
+                packet.clear();
+            }
+        }
+
+        // Mark reliable messages as sent.
+        for key in sent_keys {
+            let message = self.pending.get_mut(&key).unwrap();
+            if message.last_sent_us.is_some() {
+                self.resent += 1;
+            }
+            message.last_sent_us = Some(now_us);
+        }
+
+        self.unreliable.clear();
+        self.ack_pending = false;
+        sent
+    }
+
+    /// Processes one received packet, pushing any events that are ready to ``out``.
+    pub fn receive(&mut self, now_us: u64, packet: &[u8], out: &mut Vec<NetEvent>) {
+        let mut reader = packet;
+        let Ok(header) = bincode::deserialize_from::<_, PacketHeader>(&mut reader) else {
+            return;
+        };
+        self.last_recv_us = now_us;
+        self.ack_pending = true;
+        let acks = (self.remote_seq, self.remote_ack_bits);
+
+        // Record the packet for our acks.
+        match self.remote_seq {
+            None => self.remote_seq = Some(header.seq),
+            Some(remote_seq) if seq_greater(header.seq, remote_seq) => {
+                let shift = header.seq.wrapping_sub(remote_seq) as u32;
+                self.remote_ack_bits = match shift {
+                    1..=31 => (self.remote_ack_bits << shift) | (1 << (shift - 1)),
+                    32 => 1 << 31,
+                    _ => 0,
+                };
+                self.remote_seq = Some(header.seq);
+            }
+            Some(remote_seq) => {
+                let diff = remote_seq.wrapping_sub(header.seq) as u32;
+                if (1..=32).contains(&diff) {
+                    self.remote_ack_bits |= 1 << (diff - 1);
+                }
+            }
+        }
+
+        // Process the peer's acks.
+        self.process_ack(now_us, header.ack);
+        for bit in 0..32 {
+            if header.ack_bits & (1 << bit) != 0 {
+                self.process_ack(now_us, header.ack.wrapping_sub(bit + 1));
+            }
+        }
+
+        // Read messages. If any are refused, act like the packet was lost, so the peer resends it.
+        let mut refused = false;
+        while let Ok(message) = bincode::deserialize_from::<_, Message>(&mut reader) {
+            refused |= !self.receive_message(now_us, message, out);
+        }
+        if refused {
+            (self.remote_seq, self.remote_ack_bits) = acks;
+        }
+
+        // Give up on fragmented messages that are taking too long.
+        self.reassemblies
+            .retain(|_, r| now_us.saturating_sub(r.started_us) < REASSEMBLY_TIMEOUT_US);
+    }
+
+    /// Handles one message. Returns false if it was refused, and mustn't be acked.
+    fn receive_message(&mut self, now_us: u64, message: Message, out: &mut Vec<NetEvent>) -> bool {
+        let (id, order, event) = match message {
+            Message::Unreliable(event) => {
+                out.push(event);
+                return true;
+            }
+            Message::Reliable(id, event) => (id, None, event),
+            Message::Ordered(id, order, event) => (id, Some(order), event),
+            Message::Fragment(group, index, count, bytes) => {
+                let Some(bytes) = self.reassemble(now_us, false, group, index, count, bytes) else {
+                    return true;
+                };
+                if let Ok(message @ Message::Unreliable(..)) = bincode::deserialize(&bytes) {
+                    self.receive_message(now_us, message, out);
+                }
+                return true;
+            }
+            Message::ReliableFragment(id, index, count, bytes) => {
+                if self.is_duplicate(id) {
+                    return true;
+                }
+
+                // Reliable reassemblies can't be evicted (their fragments were acked), so new
+                // ones over the budget are refused until some finish.
+                if !self.reliable_reassemblies.contains_key(&id) {
+                    let buffered: usize = self
+                        .reliable_reassemblies
+                        .values()
+                        .map(|r| r.reserved)
+                        .sum();
+                    if buffered + count as usize * FRAGMENT_SIZE > MAX_RELIABLE_REASSEMBLY_BYTES {
+                        return false;
+                    }
+                }
+
+                let Some(bytes) = self.reassemble(now_us, true, id, index, count, bytes) else {
+                    return true;
+                };
+                match bincode::deserialize(&bytes) {
+                    Ok(message @ Message::Reliable(..)) | Ok(message @ Message::Ordered(..)) => {
+                        self.receive_message(now_us, message, out);
+                    }
+                    _ => {}
+                }
+                return true;
+            }
+        };
+
+        // Drop duplicates.
+        if self.is_duplicate(id) {
+            return true;
+        }
+
+        // An honest peer keeps its messages in flight within RELIABLE_WINDOW, so anything further
+        // ahead of the next order is refused rather than buffered without limit. (A fragmented
+        // one is lost instead, but only a misbehaving peer can send one.)
+        if let Some(order) = order {
+            if order.wrapping_sub(self.next_recv_order) >= RELIABLE_WINDOW
+                && !seq_greater(self.next_recv_order, order)
+            {
+                return false;
+            }
+        }
+        self.received_ids[id as usize % RECEIVED_ID_HISTORY] = Some(id);
+
+        match order {
+            None => out.push(event),
+            Some(order) => {
+                if seq_greater(self.next_recv_order, order) {
+                    return true;
+                }
+                self.ordered_buffer.insert(order, event);
+                while let Some(event) = self.ordered_buffer.remove(&self.next_recv_order) {
+                    out.push(event);
+                    self.next_recv_order = self.next_recv_order.wrapping_add(1);
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns true if reliable message ``id`` was already received. The sender keeps ids within
+    /// RELIABLE_WINDOW of each other, so a newer id in the same slot means it's stale too.
+    fn is_duplicate(&self, id: u16) -> bool {
+        match self.received_ids[id as usize % RECEIVED_ID_HISTORY] {
+            Some(received) => received == id || seq_greater(received, id),
+            None => false,
+        }
+    }
+
+    /// Stores a fragment, returning the whole message once every fragment has arrived. Only
+    /// unreliable reassemblies are evicted to make room, reliable ones are budgeted by the caller.
+    fn reassemble(
+        &mut self,
+        now_us: u64,
+        reliable: bool,
+        group: u16,
+        index: u16,
+        count: u16,
+        bytes: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        let (index, count) = (index as usize, count as usize);
+        if count < 2 || count > MAX_FRAGMENTS || index >= count || bytes.len() > FRAGMENT_SIZE {
+            return None;
+        }
+
+        let reassemblies = match reliable {
+            true => &mut self.reliable_reassemblies,
+            false => &mut self.reassemblies,
+        };
+
+        // Make room, evicting the oldest unreliable reassemblies first.
+        if !reliable && !reassemblies.contains_key(&group) {
+            loop {
+                let buffered: usize = reassemblies.values().map(|r| r.reserved).sum();
+                if reassemblies.len() < MAX_REASSEMBLIES
+                    && buffered + count * FRAGMENT_SIZE <= MAX_REASSEMBLY_BYTES
+                {
+                    break;
+                }
+                let (&oldest, _) = reassemblies.iter().min_by_key(|(_, r)| r.started_us)?;
+                reassemblies.remove(&oldest);
+            }
+        }
+
+        let reassembly = reassemblies.entry(group).or_insert_with(|| Reassembly {
+            parts: vec![None; count],
+            missing: count,
+            reserved: count * FRAGMENT_SIZE,
+            started_us: now_us,
+        });
+        if reassembly.parts.len() != count {
+            return None;
+        }
+        if reassembly.parts[index].is_none() {
+            reassembly.missing -= 1;
+            reassembly.parts[index] = Some(bytes);
+        }
+        if reassembly.missing > 0 {
+            return None;
+        }
+
+        let reassembly = reassemblies.remove(&group)?;
+        Some(reassembly.parts.into_iter().flatten().flatten().collect())
+    }
+
+    fn process_ack(&mut self, now_us: u64, seq: u16) {
+        let Some(sent_packet) = &mut self.sent_packets[seq as usize % SENT_PACKET_HISTORY] else {
+            return;
+        };
+        if sent_packet.seq != seq || sent_packet.acked {
+            return;
+        }
+        sent_packet.acked = true;
+
+        // Smoothed round trip time.
+        let rtt_us = now_us.saturating_sub(sent_packet.sent_us);
+        self.srtt_us = Some(match self.srtt_us {
+            Some(srtt_us) => (srtt_us * 7 + rtt_us) / 8,
+            None => rtt_us,
+        });
+
+        for key in std::mem::take(&mut sent_packet.pending_keys) {
+            self.pending.remove(&key);
+        }
+    }
+
+    fn header(&self) -> PacketHeader {
+        // Until something arrives, ack the seq before the peer's first, which is never in flight.
+        PacketHeader {
+            seq: self.next_seq,
+            ack: self.remote_seq.unwrap_or(u16::MAX),
+            ack_bits: self.remote_ack_bits,
+        }
+    }
+
+    fn resend_us(&self) -> u64 {
+        match self.srtt_us {
+            Some(srtt_us) => (srtt_us * 3 / 2 + 20_000).clamp(MIN_RESEND_US, MAX_RESEND_US),
+            None => 200_000,
+        }
+    }
+
+    /// Smoothed round trip time (in us), if any packets have been acked yet.
+    pub fn rtt_us(&self) -> Option<u64> {
+        self.srtt_us
+    }
+
+    /// Number of reliable messages waiting on an ack.
+    pub fn unacked(&self) -> usize {
+        self.pending.len()
+    }
 
-    use bincode::serialize_into;
-    use bincode::serialized_size;
+    /// Number of reliable message resends so far.
+    pub fn resent(&self) -> u64 {
+        self.resent
+    }
+}
 
-    for event in events {
-        // Get serialized event size
-        let event_size = serialized_size(&event).unwrap() as usize;
+/// A socket plus one Channel per peer. Channels are created when a peer is first sent to or heard
+/// from, and dropped once the peer has been silent for ``timeout_ms``, which is reported as a
+/// Disconnect from that peer.
+pub struct Endpoint<S: Socket = UdpSocket> {
+    socket: S,
+    timeout_ms: u64,
+    channels: HashMap<SocketAddr, Channel>,
+}
 
-        // If the event can't fit in the current packet, send the packet
-        if packet_size + event_size > PACKET_MAX_SIZE {
-            socket.send_to(&packet[..packet_size], dst).unwrap();
-            sent += packet_size;
-            packet_size = 0;
+impl<S: Socket> Endpoint<S> {
+    pub fn new(socket: S, timeout_ms: u64) -> Self {
+        Self {
+            socket,
+            timeout_ms,
+            channels: HashMap::new(),
         }
+    }
+
+    pub fn socket(&self) -> &S {
+        &self.socket
+    }
+
+    pub fn channel(&self, addr: SocketAddr) -> Option<&Channel> {
+        self.channels.get(&addr)
+    }
+
+    /// Forgets a peer.
+    pub fn remove(&mut self, addr: SocketAddr) {
+        self.channels.remove(&addr);
+    }
 
-        // Pack net event into packet
-        serialize_into(&mut packet[packet_size..], &event).unwrap();
-        packet_size += event_size;
+    /// Queues ``events`` for ``dst`` and flushes its channel. Returns the number of bytes sent, or
+    /// an error if an event couldn't be queued, in which case the events after it weren't either.
+    pub fn send_to(&mut self, dst: SocketAddr, events: &[NetEvent]) -> Result<usize> {
+        let channel = self.channels.entry(dst).or_insert_with(Channel::new);
+        for event in events {
+            channel.queue(event.clone())?;
+        }
+        let socket = &self.socket;
+        Ok(channel.flush(get_microseconds_as_u64(), |packet| {
+            let _ = socket.send_to(packet, dst);
+        }))
     }
 
-    // Send last packet if needed
-    if packet_size != 0 {
-        socket.send_to(&packet[..packet_size], dst).unwrap();
-        sent += packet_size;
+    /// Receives every waiting packet. Returns the number of bytes received.
+    pub fn recv_from(&mut self, vec: &mut Vec<(NetEvent, SocketAddr)>) -> usize {
+        let mut packet = [0u8; PACKET_MAX_SIZE];
+        let mut received = 0;
+        let mut events = vec![];
+        let now_us = get_microseconds_as_u64();
+
+        // While there are packets...
+        loop {
+            let (n, src) = match self.socket.recv_from(&mut packet) {
+                Ok(v) => v,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => break,
+                Err(e) => {
+                    println!("[Net] Could not receive: {e}");
+                    break;
+                }
+            };
+            received += n;
+            let channel = self.channels.entry(src).or_insert_with(Channel::new);
+            channel.receive(now_us, &packet[..n], &mut events);
+            vec.extend(events.drain(..).map(|event| (event, src)));
+        }
+
+        // Drop channels of silent peers. Anything heard from them later starts a new channel, so
+        // whatever was in flight on the old one is gone, and the session with it.
+        let timeout_us = self.timeout_ms * 1_000;
+        self.channels.retain(|&addr, channel| {
+            let alive = now_us.saturating_sub(channel.last_recv_us) < timeout_us;
+            if !alive {
+                vec.push((NetEvent::Disconnect, addr));
+            }
+            alive
+        });
+
+        received
     }
+}
 
-    return sent;
+/// An in-process network of LossySockets, which drops, duplicates and reorders packets at the
+/// given rates.
+#[derive(Clone)]
+pub struct LossyNetwork {
+    inboxes: Arc<Mutex<HashMap<SocketAddr, VecDeque<(Vec<u8>, SocketAddr)>>>>,
+    rng: Arc<Mutex<u64>>,
+    pub loss: f32,
+    pub duplicate: f32,
+    pub reorder: f32,
 }
 
-pub fn recv(socket: &UdpSocket, vec: &mut Vec<NetEvent>) -> usize {
-    let mut packet = [0u8; PACKET_MAX_SIZE];
-    let mut received = 0;
+impl LossyNetwork {
+    pub fn new(loss: f32, duplicate: f32, reorder: f32, seed: u64) -> Self {
+        Self {
+            inboxes: Arc::new(Mutex::new(HashMap::new())),
+            rng: Arc::new(Mutex::new(seed | 1)),
+            loss,
+            duplicate,
+            reorder,
+        }
+    }
+
+    pub fn bind(&self, addr: SocketAddr) -> LossySocket {
+        self.inboxes.lock().unwrap().entry(addr).or_default();
+        LossySocket {
+            addr,
+            network: self.clone(),
+        }
+    }
+
+    // xorshift64, mapped to [0, 1).
+    fn roll(&self) -> f32 {
+        let mut state = self.rng.lock().unwrap();
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        (*state >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
 
-    use std::io::BufReader;
+pub struct LossySocket {
+    addr: SocketAddr,
+    network: LossyNetwork,
+}
 
-    // While there are packets...
-    while let Ok((n, _)) = socket.recv_from(&mut packet) {
-        let mut reader = BufReader::new(&packet[..n]);
-        received += n;
-        // While there is still data to deserialize
-        while let Ok(event) = bincode::deserialize_from::<_, NetEvent>(&mut reader) {
-            vec.push(event);
+impl Socket for LossySocket {
+    fn send_to(&self, buf: &[u8], dst: SocketAddr) -> Result<usize> {
+        let network = &self.network;
+        let copies = match (
+            network.roll() < network.loss,
+            network.roll() < network.duplicate,
+        ) {
+            (true, _) => 0,
+            (false, true) => 2,
+            (false, false) => 1,
+        };
+        let reorder = network.roll() < network.reorder;
+        let mut inboxes = network.inboxes.lock().unwrap();
+        if let Some(inbox) = inboxes.get_mut(&dst) {
+            for _ in 0..copies {
+                match reorder {
+                    true => inbox.push_front((buf.to_vec(), self.addr)),
+                    false => inbox.push_back((buf.to_vec(), self.addr)),
+                }
+            }
         }
+        Ok(buf.len())
     }
 
-    return received;
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let mut inboxes = self.network.inboxes.lock().unwrap();
+        match inboxes
+            .get_mut(&self.addr)
+            .and_then(|inbox| inbox.pop_front())
+        {
+            Some((packet, src)) => {
+                let n = packet.len().min(buf.len());
+                buf[..n].copy_from_slice(&packet[..n]);
+                Ok((n, src))
+            }
+            None => Err(ErrorKind::WouldBlock.into()),
+        }
+    }
 }
 
-pub fn recv_from(socket: &UdpSocket, vec: &mut Vec<(NetEvent, SocketAddr)>) -> usize {
-    let mut packet = [0u8; PACKET_MAX_SIZE];
-    let mut received = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    // Two channels, at addr(1) and addr(2), talking over a LossyNetwork.
+    struct Link {
+        a: LossySocket,
+        b: LossySocket,
+        channel_a: Channel,
+        channel_b: Channel,
+        frame: u64,
+    }
+
+    impl Link {
+        fn new(network: &LossyNetwork) -> Self {
+            Self {
+                a: network.bind(addr(1)),
+                b: network.bind(addr(2)),
+                channel_a: Channel::new(),
+                channel_b: Channel::new(),
+                frame: 0,
+            }
+        }
+
+        // Runs ``frames`` 60hz frames of both sides flushing, so acks and resends go out. Pushes
+        // everything addr(2) receives to ``out``.
+        fn run(&mut self, frames: u64, out: &mut Vec<NetEvent>) {
+            let mut packet = [0u8; PACKET_MAX_SIZE];
+            let mut replies = vec![];
+            for _ in 0..frames {
+                let now_us = self.frame * 16_666;
+                self.frame += 1;
+                let (a, b) = (&self.a, &self.b);
+                self.channel_a.flush(now_us, |p| {
+                    let _ = a.send_to(p, addr(2));
+                });
+                while let Ok((n, _)) = b.recv_from(&mut packet) {
+                    self.channel_b.receive(now_us, &packet[..n], out);
+                }
+                self.channel_b.flush(now_us, |p| {
+                    let _ = b.send_to(p, addr(1));
+                });
+                while let Ok((n, _)) = a.recv_from(&mut packet) {
+                    self.channel_a.receive(now_us, &packet[..n], &mut replies);
+                }
+            }
+        }
+    }
+
+    // Sends ``events`` from addr(1) to addr(2) over ``frames`` frames. Returns everything addr(2)
+    // received, and the sending channel.
+    fn transfer(
+        network: &LossyNetwork,
+        events: Vec<NetEvent>,
+        frames: u64,
+    ) -> (Vec<NetEvent>, Channel) {
+        let mut link = Link::new(network);
+        for event in events {
+            link.channel_a.queue(event).unwrap();
+        }
+        let mut received = vec![];
+        link.run(frames, &mut received);
+        (received, link.channel_a)
+    }
 
-    use std::io::BufReader;
+    #[test]
+    fn lossy_network_loses_duplicates_and_reorders() {
+        let network = LossyNetwork::new(0.2, 0.2, 0.2, 1);
+        let (a, b) = (network.bind(addr(1)), network.bind(addr(2)));
+        for i in 0..1000u16 {
+            a.send_to(&i.to_le_bytes(), addr(2)).unwrap();
+        }
 
-    // While there are packets...
-    while let Ok((n, src)) = socket.recv_from(&mut packet) {
-        let mut reader = BufReader::new(&packet[..n]);
-        received += n;
-        // While there is still data to deserialize
-        while let Ok(event) = bincode::deserialize_from::<_, NetEvent>(&mut reader) {
-            vec.push((event, src));
+        let mut buf = [0u8; 2];
+        let mut received = vec![];
+        while let Ok((2, src)) = b.recv_from(&mut buf) {
+            assert_eq!(src, addr(1));
+            received.push(u16::from_le_bytes(buf));
         }
+        let mut unique = received.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert!(unique.len() < 1000, "nothing was lost");
+        assert!(received.len() > unique.len(), "nothing was duplicated");
+        assert!(
+            received.windows(2).any(|w| w[0] > w[1]),
+            "nothing was reordered"
+        );
+    }
+
+    #[test]
+    fn reliable_unordered_arrives_exactly_once() {
+        let network = LossyNetwork::new(0.2, 0.2, 0.3, 2);
+        let events = (0..300).map(|x| NetEvent::RequestChunk(x, 0)).collect();
+        let (received, channel) = transfer(&network, events, 600);
+
+        let mut xs: Vec<u16> = received
+            .into_iter()
+            .map(|event| match event {
+                NetEvent::RequestChunk(x, 0) => x,
+                event => panic!("unexpected {event:?}"),
+            })
+            .collect();
+        xs.sort_unstable();
+        assert_eq!(xs, Vec::from_iter(0..300));
+        assert_eq!(channel.unacked(), 0);
+        assert!(channel.resent() > 0);
+    }
+
+    #[test]
+    fn reliable_ordered_arrives_in_order() {
+        // Every 7th message is big enough to be split into fragments.
+        let network = LossyNetwork::new(0.2, 0.2, 0.3, 3);
+        let message = |i: usize| format!("{i}:{}", "x".repeat(if i % 7 == 0 { 3000 } else { 3 }));
+        let events = (0..300)
+            .map(|i| NetEvent::ChatMessage(message(i)))
+            .collect();
+        let (received, channel) = transfer(&network, events, 1200);
+
+        let messages: Vec<String> = received
+            .into_iter()
+            .map(|event| match event {
+                NetEvent::ChatMessage(message) => message,
+                event => panic!("unexpected {event:?}"),
+            })
+            .collect();
+        assert_eq!(messages, Vec::from_iter((0..300).map(message)));
+        assert_eq!(channel.unacked(), 0);
+    }
+
+    #[test]
+    fn unreliable_is_never_resent() {
+        let network = LossyNetwork::new(0.5, 0., 0., 4);
+        let events = (0..200).map(NetEvent::WorldTime).collect();
+        let (received, channel) = transfer(&network, events, 100);
+
+        let mut times: Vec<u64> = received
+            .into_iter()
+            .map(|event| match event {
+                NetEvent::WorldTime(t) => t,
+                event => panic!("unexpected {event:?}"),
+            })
+            .collect();
+        let count = times.len();
+        times.sort_unstable();
+        times.dedup();
+        assert_eq!(times.len(), count, "something arrived twice");
+        assert!(count < 200, "nothing was lost");
+        assert_eq!(channel.resent(), 0);
     }
 
-    return received;
+    #[test]
+    fn reliable_fragments_over_budget_are_resent() {
+        // Fill the receiver's budget, like a peer that left lots of big messages unfinished.
+        let mut link = Link::new(&LossyNetwork::new(0., 0., 0., 5));
+        let unfinished = Reassembly {
+            parts: vec![None; 2],
+            missing: 2,
+            reserved: MAX_RELIABLE_REASSEMBLY_BYTES,
+            started_us: 0,
+        };
+        link.channel_b
+            .reliable_reassemblies
+            .insert(RELIABLE_WINDOW * 2, unfinished);
+
+        // Refused fragments aren't acked...
+        let message = "x".repeat(3000);
+        link.channel_a
+            .queue(NetEvent::ChatMessage(message.clone()))
+            .unwrap();
+        let mut received = vec![];
+        link.run(60, &mut received);
+        assert!(received.is_empty());
+        assert!(link.channel_a.unacked() > 0);
+
+        // ...so they arrive once there's room.
+        link.channel_b.reliable_reassemblies.clear();
+        link.run(60, &mut received);
+        assert!(matches!(&received[..], [NetEvent::ChatMessage(m)] if *m == message));
+        assert_eq!(link.channel_a.unacked(), 0);
+    }
+
+    #[test]
+    fn oversized_messages_are_refused_without_using_an_order() {
+        let mut link = Link::new(&LossyNetwork::new(0., 0., 0., 6));
+        let huge = NetEvent::ChatMessage("x".repeat(MAX_MESSAGE_SIZE));
+        assert!(link.channel_a.queue(huge).is_err());
+
+        // Later ordered messages aren't held back waiting for it.
+        let message = String::from("after");
+        link.channel_a
+            .queue(NetEvent::ChatMessage(message.clone()))
+            .unwrap();
+        let mut received = vec![];
+        link.run(10, &mut received);
+        assert!(matches!(&received[..], [NetEvent::ChatMessage(m)] if *m == message));
+    }
+
+    #[test]
+    fn reliable_budget_counts_whole_messages() {
+        // Only the first fragment of each arrives, so little is actually buffered.
+        let mut channel = Channel::new();
+        let count = MAX_FRAGMENTS as u16;
+        let fragment = |id| Message::ReliableFragment(id, 0, count, vec![0; FRAGMENT_SIZE]);
+        let admitted = (0..RELIABLE_WINDOW)
+            .filter(|&id| channel.receive_message(0, fragment(id), &mut vec![]))
+            .count();
+        assert_eq!(admitted, MAX_RELIABLE_REASSEMBLY_BYTES / MAX_MESSAGE_SIZE);
+    }
+
+    #[test]
+    fn ordered_messages_too_far_ahead_are_refused() {
+        let mut channel = Channel::new();
+        let mut out = vec![];
+        let chat = |i: u16| Message::Ordered(i, i, NetEvent::ChatMessage(i.to_string()));
+        assert!(!channel.receive_message(0, chat(RELIABLE_WINDOW), &mut out));
+        assert!(
+            !channel.is_duplicate(RELIABLE_WINDOW),
+            "refused, so it can be resent"
+        );
+        assert!(channel.receive_message(0, chat(RELIABLE_WINDOW - 1), &mut out));
+        assert!(channel.receive_message(0, chat(0), &mut out));
+        assert_eq!(out.len(), 1);
+        assert_eq!(channel.ordered_buffer.len(), 1);
+    }
 }
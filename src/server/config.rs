@@ -0,0 +1,168 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::game::tile::*;
+
+pub const SERVER_USAGE: &str = "\
+Usage: server [options]
+  --config <path>        Load settings from a TOML file (flags override it)
+  --bind <address>       Address to bind to
+  --port <port>          Port to bind to
+  --world-size <w>x<h>   World size in chunks, used when generating a new world
+  --seed <seed>          World seed, used when generating a new world
+  --tick-rate <hz>       Simulation ticks per second
+  --max-players <n>      Maximum number of connected players
+  --save <path>          World save file
+  --timeout <ms>         Drop connections that are silent for this long
+  --item-lifetime <s>    Despawn items left on the ground this long
+  --ban <name|ip>        Refuse a player name or IP address (may be repeated)
+  --protect <x1,y1,x2,y2>
+                         Tiles players can't edit, from x1,y1 up to x2,y2 (may be repeated)
+  --help                 Print this message
+
+Console commands (typed while the server runs):
+  stop                   Save the world and shut down
+  time                   Print the time of day
+  time <HH:MM|dawn|noon|dusk|midnight>
+                         Set the time of day";
+
+/// Server settings. Loaded from an optional TOML file, then overridden by command line flags.
+/// Every field may be left out of the file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    pub world_w: usize, // In chunks.
+    pub world_h: usize, // In chunks.
+    pub seed: Option<u64>,
+    pub tick_rate: u64, // In ticks per second.
+    pub max_players: usize,
+    pub save_path: PathBuf,
+    pub timeout_ms: u64,
+    pub item_lifetime_s: u64,
+    pub banned: Vec<String>,                  // Player names or IP addresses.
+    pub protected: Vec<(u16, u16, u16, u16)>, // Tile regions (x1, y1, x2, y2), end exclusive.
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: String::from("0.0.0.0"),
+            port: 0xCAFE,
+            world_w: 512,
+            world_h: 128,
+            seed: None,
+            tick_rate: 10,
+            max_players: 16,
+            save_path: crate::io::get_root().join("world.sav"),
+            timeout_ms: 5_000,
+            item_lifetime_s: 300,
+            banned: vec![],
+            protected: vec![],
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Builds a config from command line arguments (excluding the program name).
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let mut config = None;
+        let mut overrides = vec![];
+
+        while let Some(flag) = args.next() {
+            if flag == "--help" {
+                return Err(String::from(SERVER_USAGE));
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {flag}\n\n{SERVER_USAGE}"))?;
+            match flag.as_str() {
+                "--config" => config = Some(PathBuf::from(value)),
+                _ => overrides.push((flag, value)),
+            }
+        }
+
+        // Load the file first so flags take priority.
+        let mut out = match config {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        for (flag, value) in overrides {
+            out.apply_flag(&flag, &value)?;
+        }
+
+        out.validate()?;
+        Ok(out)
+    }
+
+    pub fn from_file(path: &std::path::Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read config {path:?}: {e}"))?;
+        toml::from_str(&text).map_err(|e| format!("could not parse config {path:?}: {e}"))
+    }
+
+    fn apply_flag(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value for {flag}: {value:?}"))
+        }
+
+        match flag {
+            "--bind" => self.bind_address = String::from(value),
+            "--port" => self.port = parse(flag, value)?,
+            "--world-size" => {
+                let (w, h) = value
+                    .split_once('x')
+                    .ok_or_else(|| format!("invalid value for {flag}: {value:?}"))?;
+                self.world_w = parse(flag, w)?;
+                self.world_h = parse(flag, h)?;
+            }
+            "--seed" => self.seed = Some(parse(flag, value)?),
+            "--tick-rate" => self.tick_rate = parse(flag, value)?,
+            "--max-players" => self.max_players = parse(flag, value)?,
+            "--save" => self.save_path = PathBuf::from(value),
+            "--timeout" => self.timeout_ms = parse(flag, value)?,
+            "--item-lifetime" => self.item_lifetime_s = parse(flag, value)?,
+            "--ban" => self.banned.push(String::from(value)),
+            "--protect" => {
+                let v: Vec<u16> = value
+                    .split(',')
+                    .map(|v| parse(flag, v))
+                    .collect::<Result<_, _>>()?;
+                let &[x1, y1, x2, y2] = &v[..] else {
+                    return Err(format!("invalid value for {flag}: {value:?}"));
+                };
+                self.protected.push((x1, y1, x2, y2));
+            }
+            _ => return Err(format!("unknown option {flag}\n\n{SERVER_USAGE}")),
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.tick_rate == 0 || self.tick_rate > 1_000 {
+            return Err(String::from("tick rate must be between 1 and 1000"));
+        }
+        // Tile coordinates are sent as u16.
+        let max_chunks = u16::MAX as usize / CHUNK_SIZE;
+        if self.world_w == 0
+            || self.world_h == 0
+            || self.world_w > max_chunks
+            || self.world_h > max_chunks
+        {
+            return Err(format!(
+                "world size must be between 1 and {max_chunks} chunks"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Length of one simulation tick (in us).
+    pub fn frametime_us(&self) -> u64 {
+        1_000_000 / self.tick_rate
+    }
+}
@@ -1,21 +1,81 @@
 use crate::array2d::*;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use super::config::*;
+use super::save::*;
+use super::world_gen::*;
 
 use crate::game::collision::*;
+use crate::game::delta::*;
 use crate::game::humanoid::*;
+use crate::game::inventory::*;
 use crate::game::item::*;
 use crate::game::net::*;
+use crate::game::sky::*;
 use crate::game::tile::*;
 
+// How often the world is written to disk.
+const AUTOSAVE_INTERVAL_US: u64 = 60_000_000;
+
+// How often clients are sent the time of day. They keep the clock running in between.
+const TIME_SYNC_INTERVAL_US: u64 = 1_000_000;
+
+// Player input.
+const MAX_QUEUED_INPUTS: usize = 64; // Per humanoid, extra inputs are dropped.
+const MAX_INPUT_BUDGET_US: u64 = 250_000; // How far behind a client may fall and then catch up.
+
+// Snapshots kept as delta baselines. Clients that fall further behind get a full snapshot.
+const SNAPSHOT_HISTORY: usize = 32;
+
+// Chunk streaming, per connection.
+const MAX_QUEUED_CHUNKS: usize = 1024; // Requests past this are ignored.
+const CHUNK_BYTES_PER_SECOND: u64 = 64 * 1024;
+const MAX_CHUNK_BUDGET: u64 = 16 * 1024; // The most sent at once, after a quiet period.
+
+// Tile edits.
+const EDIT_COOLDOWN_MS: u64 = 100; // Per player.
+const REACH_TOLERANCE_PX: f32 = 2. * TILE_SIZE as f32; // The server's view of a player lags behind.
+const WORLD_BORDER: usize = 2; // Tiles at the edge of the world that can't be edited.
+
+// Items.
+const ITEM_MAGNET_RADIUS: f32 = 4. * TILE_SIZE as f32; // Items this close fly to a player.
+const ITEM_MAGNET_SPEED: f32 = 20. * TILE_SIZE as f32;
+const ITEM_MERGE_RADIUS: f32 = TILE_SIZE as f32; // Items of a kind this close become one stack.
+
+// Area of interest. Players are only sent the entities around their view.
+const DEFAULT_VIEW_SIZE: (u16, u16) = (1080, 720); // Until the client says otherwise.
+const MAX_VIEW_SIZE: (u16, u16) = (3840, 2160); // A bigger window doesn't see further.
+const INTEREST_MARGIN_PX: f32 = (CHUNK_LOAD_BUFFER_SIZE * TILE_SIZE) as f32; // Past the view.
+const INTEREST_HYSTERESIS_PX: f32 = 4. * TILE_SIZE as f32; // Extra, before despawning.
+
 pub struct GameUpdate {
     kill: bool,
 
+    // Persistence.
+    seed: u64,
+    save_path: PathBuf,
+    next_autosave_us: u64,
+
     connections: HashMap<SocketAddr, Connection>,
+    rejections: Vec<(SocketAddr, NetEvent)>, // Sent to addresses that aren't connected.
+    max_players: usize,
+    timeout_ms: u64,
+    banned: Vec<String>,
+    protected: Vec<(u16, u16, u16, u16)>,
+
+    // Snapshots.
+    next_snapshot_id: u32,
+
+    // World clock.
+    time_of_day_us: u64,
+    next_time_sync_us: u64,
 
     // Items.
     item_id_counter: u64,
     items: BTreeMap<u64, Item>,
+    item_lifetime_us: u64,
 
     // Tiles.
     world_w: usize,
@@ -29,54 +89,114 @@ pub struct GameUpdate {
 }
 
 impl GameUpdate {
-    pub fn new() -> Self {
-        // World size.
-        let world_w = CHUNK_SIZE * 512;
-        let world_h = CHUNK_SIZE * 128;
-
-        // Create 1024 * 256 chunk world.
-        let foreground_tiles = Array2D::from_closure(world_w, world_h, |x, y| {
-            let h = (7.0 * ((x as f32 / 4.).sin() + 1.0) / 2.0) as usize + 15;
-            if y < h {
-                return Tile::None;
+    pub fn new(config: &ServerConfig) -> Self {
+        let save_path = config.save_path.clone();
+
+        // Load the world from disk, or create a new one if there is none.
+        let save = match load_world(&save_path) {
+            Ok(save) => {
+                println!(
+                    "[Server] Loaded world from {:?} (seed {}).",
+                    save_path, save.seed
+                );
+                save
             }
-            if y - h < 5 {
-                return Tile::Dirt;
+            Err(e) => {
+                // Keep a save that can't be loaded (corrupt, or from a newer version), or the
+                // first autosave would overwrite it.
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    match set_aside_world(&save_path) {
+                        Ok(bad_path) => println!(
+                            "[Server] Could not load world from {save_path:?}: {e}. Moved it to \
+                             {bad_path:?}."
+                        ),
+                        Err(e2) => {
+                            eprintln!(
+                                "[Server] Could not load world from {save_path:?}: {e}, nor move \
+                                 it out of the way: {e2}."
+                            );
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                let seed = config.seed.unwrap_or_else(|| {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap()
+                        .as_nanos() as u64
+                });
+                println!("[Server] Generating new world with seed {seed}.");
+                let (w, h) = (config.world_w * CHUNK_SIZE, config.world_h * CHUNK_SIZE);
+                generate_world(&OverworldGenerator::default(), seed, w, h)
             }
-            return Tile::Stone;
-        });
+        };
 
-        // Temp item.
-        let item_id_counter = 1;
-        let items = BTreeMap::from_iter(std::iter::once((
-            0,
-            Item {
-                x: 48.,
-                y: 32.,
-                dx: 0.,
-                dy: 0.,
-                id: ItemId::Dirt,
-            },
-        )));
+        let world_w = save.world_w as usize;
+        let world_h = save.world_h as usize;
+        let to_tiles = |raw: Vec<u8>| {
+            let tiles = raw
+                .into_iter()
+                .map(|t| Tile::try_from(t).unwrap_or(Tile::NONE));
+            Array2D::from_box(world_w, world_h, tiles.collect())
+        };
 
         Self {
             kill: false,
 
+            seed: save.seed,
+            save_path,
+            next_autosave_us: crate::time::get_microseconds_as_u64() + AUTOSAVE_INTERVAL_US,
+
             connections: HashMap::new(),
+            rejections: vec![],
+            max_players: config.max_players,
+            timeout_ms: config.timeout_ms,
+            banned: config.banned.clone(),
+            protected: config.protected.clone(),
+
+            next_snapshot_id: 0,
 
-            item_id_counter,
-            items,
+            time_of_day_us: save.time_of_day_us % DAY_LENGTH_US,
+            next_time_sync_us: 0,
+
+            item_id_counter: save.item_id_counter,
+            items: save.items,
+            item_lifetime_us: config.item_lifetime_s * 1_000_000,
 
             world_w,
             world_h,
-            background_tiles: foreground_tiles.clone_sub(0..world_w, 0..world_h).unwrap(),
-            foreground_tiles,
+            foreground_tiles: to_tiles(save.foreground_tiles),
+            background_tiles: to_tiles(save.background_tiles),
 
-            humanoid_id_counter: 0,
+            humanoid_id_counter: save.humanoid_id_counter,
             humanoids: BTreeMap::new(),
         }
     }
 
+    /// Writes the persistent part of the world to ``save_path``.
+    pub fn save(&self) {
+        let to_raw = |tiles: &Array2D<Tile>| tiles.raw().iter().map(|&t| t.0).collect();
+        let save = WorldSave {
+            seed: self.seed,
+            world_w: self.world_w as u32,
+            world_h: self.world_h as u32,
+            foreground_tiles: to_raw(&self.foreground_tiles),
+            background_tiles: to_raw(&self.background_tiles),
+            item_id_counter: self.item_id_counter,
+            items: self.items.clone(),
+            humanoid_id_counter: self.humanoid_id_counter,
+            time_of_day_us: self.time_of_day_us,
+        };
+
+        match save_world(&self.save_path, &save) {
+            Ok(()) => println!("[Server] Saved world to {:?}.", self.save_path),
+            Err(e) => println!(
+                "[Server] Could not save world to {:?}: {e}.",
+                self.save_path
+            ),
+        }
+    }
+
     pub fn preframe(
         &mut self,
         timestamp_us: u64,
@@ -108,7 +228,19 @@ impl GameUpdate {
 
         for (event, addr) in net_events {
             // Handle connect.
-            if matches!(event, NetEvent::Connect) && !self.connections.contains_key(&addr) {
+            if let NetEvent::Connect(version, build_hash, nonce, name) = &event {
+                if self.connections.contains_key(&addr) {
+                    continue;
+                }
+
+                // Turn away anyone who can't or shouldn't join.
+                if let Some(reason) = self.check_connect(addr, *version, *build_hash, name) {
+                    println!("[Server] {addr:?} ({name:?}) was refused: {reason}.");
+                    self.rejections
+                        .push((addr, NetEvent::Reject(*nonce, reason)));
+                    continue;
+                }
+
                 // Get an id
                 let humanoid_id = self.humanoid_id_counter;
                 self.humanoid_id_counter += 1;
@@ -127,21 +259,41 @@ impl GameUpdate {
                         dy: 0.,
                         grounded: true,
                     },
+                    inputs: VecDeque::new(),
+                    last_input_frame: None,
+                    applied_input_frame: None,
+                    input_budget_us: 0,
+                    inventory: Inventory::new(),
+                    inventory_changed: false,
+                    selected_slot: 0,
+                    mining: None,
                 };
 
                 // Create event vec with Accept event.
-                let net_events = vec![NetEvent::Accept(
-                    self.world_w as u16,
-                    self.world_h as u16,
-                    humanoid_id,
-                )];
+                let net_events = vec![
+                    NetEvent::Accept(
+                        *nonce,
+                        self.world_w as u16,
+                        self.world_h as u16,
+                        humanoid_id,
+                    ),
+                    NetEvent::UpdateInventory(humanoid.inventory.clone()),
+                    NetEvent::WorldTime(self.time_of_day_us),
+                ];
 
                 // Establish connection.
-                println!("[Server] {:?} has connected.", addr);
+                println!("[Server] {addr:?} has connected as {name:?}.");
                 self.humanoids.insert(humanoid_id, humanoid);
                 let _connection = self.connections.entry(addr).or_insert_with(|| Connection {
-                    last_msg: timestamp_ms as u16,
+                    name: name.clone(),
+                    last_msg: timestamp_ms,
                     humanoid_id,
+                    view_size: DEFAULT_VIEW_SIZE,
+                    acked_snapshot: None,
+                    snapshots: VecDeque::new(),
+                    chunk_requests: vec![],
+                    chunk_budget: MAX_CHUNK_BUDGET,
+                    next_edit_ms: 0,
                     net_events,
                 });
 
@@ -154,78 +306,192 @@ impl GameUpdate {
             };
 
             // Handle net message.
-            connection.last_msg = timestamp_ms as u16;
+            connection.last_msg = timestamp_ms;
             match event {
-                NetEvent::Connect => {
+                NetEvent::Connect(..) => {
                     // Connection handling is done above.
                     //unreachable!()
                 }
-                NetEvent::Disconnect => {
+                // Only the console can shut the server down, so a Close just leaves too.
+                NetEvent::Disconnect | NetEvent::Close => {
+                    println!("Disconnected {addr:?}.");
+                    self.humanoids.remove(&connection.humanoid_id);
                     self.connections.remove(&addr);
                 }
-                NetEvent::UpdateHumanoid(id, physics) => {
-                    let Some(humanoid) = self.humanoids.get_mut(&id) else {
+                NetEvent::ViewSize(w, h) => {
+                    connection.view_size = (w.min(MAX_VIEW_SIZE.0), h.min(MAX_VIEW_SIZE.1));
+                }
+                NetEvent::AckSnapshot(id) => {
+                    // Ignore acks for snapshots that weren't sent yet.
+                    if id < self.next_snapshot_id && connection.acked_snapshot < Some(id) {
+                        connection.acked_snapshot = Some(id);
+                    }
+                }
+                NetEvent::PlayerInput(inputs) => {
+                    let Some(humanoid) = self.humanoids.get_mut(&connection.humanoid_id) else {
                         continue;
                     };
 
-                    humanoid.physics = physics;
+                    // Inputs are resent until they're surely received, so skip ones already seen.
+                    for input in inputs {
+                        if humanoid
+                            .last_input_frame
+                            .map_or(false, |last| input.frame <= last)
+                        {
+                            continue;
+                        }
+                        if humanoid.inputs.len() >= MAX_QUEUED_INPUTS {
+                            break;
+                        }
+                        humanoid.last_input_frame = Some(input.frame);
+                        humanoid.inputs.push_back(input);
+                    }
                 }
-                NetEvent::Close => self.kill = true,
                 NetEvent::RequestChunk(x, y) => {
-                    let xr = CHUNK_SIZE * x as usize..CHUNK_SIZE * (x as usize + 1);
-                    let yr = CHUNK_SIZE * y as usize..CHUNK_SIZE * (y as usize + 1);
-                    let fg = self
-                        .foreground_tiles
-                        .clone_sub(xr.clone(), yr.clone())
-                        .unwrap();
-                    let bg = self
-                        .background_tiles
-                        .clone_sub(xr.clone(), yr.clone())
-                        .unwrap();
-                    connection.net_events.push(NetEvent::UpdateForegroundChunk(
-                        x,
-                        y,
-                        fg.into_raw(),
-                    ));
-                    connection.net_events.push(NetEvent::UpdateBackgroundChunk(
-                        x,
-                        y,
-                        bg.into_raw(),
-                    ));
+                    // Queue it, it's sent in postframe.
+                    let in_world = CHUNK_SIZE * (x as usize + 1) <= self.world_w
+                        && CHUNK_SIZE * (y as usize + 1) <= self.world_h;
+                    let requests = &mut connection.chunk_requests;
+                    if in_world && requests.len() < MAX_QUEUED_CHUNKS && !requests.contains(&(x, y))
+                    {
+                        requests.push((x, y));
+                    }
                 }
-                NetEvent::BreakForeground(x, y) => {
-                    match self.foreground_tiles.get_mut(x as _, y as _) {
-                        Some(tile) => {
-                            *tile = Tile::None;
-                            partial_broadcast(
-                                &mut self.connections,
-                                addr,
-                                NetEvent::UpdateForegroundTile(x, y, Tile::None),
-                            );
+                NetEvent::BreakForeground(x, y) | NetEvent::BreakBackground(x, y) => {
+                    let foreground = matches!(event, NetEvent::BreakForeground(..));
+                    let (tiles, update): (_, fn(u16, u16, Tile) -> NetEvent) = match foreground {
+                        true => (&self.foreground_tiles, NetEvent::UpdateForegroundTile),
+                        false => (&self.background_tiles, NetEvent::UpdateBackgroundTile),
+                    };
+                    let Some(&tile) = tiles.get(x as _, y as _) else {
+                        continue;
+                    };
+                    let Some(humanoid) = self.humanoids.get_mut(&connection.humanoid_id) else {
+                        continue;
+                    };
+
+                    // Refused targets send back the real tile, in case the client's is stale.
+                    let world = (self.world_w, self.world_h);
+                    let check = match tile == Tile::NONE {
+                        true => Err("nothing there"),
+                        false => {
+                            check_tile_edit(Some(&humanoid.physics), x, y, world, &self.protected)
                         }
-                        None => {}
+                    };
+                    if let Err(reason) = check {
+                        println!("[Server] {addr:?} can't mine tile ({x}, {y}): {reason}.");
+                        connection.net_events.push(update(x, y, tile));
+                        connection.net_events.push(NetEvent::StopMining);
+                        humanoid.mining = None;
+                        continue;
+                    }
+
+                    // Keep going if it's the same tile, the tile breaks in step.
+                    let target = (x, y, foreground);
+                    if humanoid.mining.map(|m| (m.x, m.y, m.foreground)) != Some(target) {
+                        humanoid.mining = Some(Mining {
+                            x,
+                            y,
+                            foreground,
+                            progress_us: 0,
+                        });
                     }
                 }
-                NetEvent::BreakBackground(x, y) => {
-                    match self.background_tiles.get_mut(x as _, y as _) {
-                        Some(tile) => {
-                            *tile = Tile::None;
-                            partial_broadcast(
-                                &mut self.connections,
-                                addr,
-                                NetEvent::UpdateBackgroundTile(x, y, Tile::None),
-                            );
-                        }
-                        None => {}
+                NetEvent::StopMining => {
+                    if let Some(humanoid) = self.humanoids.get_mut(&connection.humanoid_id) {
+                        humanoid.mining = None;
                     }
                 }
+                NetEvent::SelectSlot(slot) => {
+                    let Some(humanoid) = self.humanoids.get_mut(&connection.humanoid_id) else {
+                        continue;
+                    };
+                    if (slot as usize) < HOTBAR_SIZE {
+                        humanoid.selected_slot = slot as usize;
+                    }
+                }
+                NetEvent::InventoryAction(action) => {
+                    let Some(humanoid) = self.humanoids.get_mut(&connection.humanoid_id) else {
+                        continue;
+                    };
+                    if humanoid.inventory.apply(action) {
+                        humanoid.inventory_changed = true;
+                    }
+                }
+                NetEvent::PlaceForeground(x, y) | NetEvent::PlaceBackground(x, y) => {
+                    let foreground = matches!(event, NetEvent::PlaceForeground(..));
+                    let update = match foreground {
+                        true => NetEvent::UpdateForegroundTile,
+                        false => NetEvent::UpdateBackgroundTile,
+                    };
+                    let tiles = match foreground {
+                        true => &self.foreground_tiles,
+                        false => &self.background_tiles,
+                    };
+                    let Some(&tile) = tiles.get(x as _, y as _) else {
+                        continue;
+                    };
+                    let Some(humanoid) = self.humanoids.get(&connection.humanoid_id) else {
+                        continue;
+                    };
+
+                    // Refused placements send back the real tile, undoing the client's prediction.
+                    let world = (self.world_w, self.world_h);
+                    let slot = humanoid.selected_slot;
+                    let stack = humanoid.inventory.get(slot);
+                    let placed = stack.and_then(|stack| stack.id.placed_tile());
+                    let check = match placed {
+                        _ if timestamp_ms < connection.next_edit_ms => {
+                            Err("too soon after the last edit")
+                        }
+                        None => Err("nothing placeable in that slot"),
+                        Some(placed) => {
+                            let (fg, bg) = (&self.foreground_tiles, &self.background_tiles);
+                            check_tile_edit(Some(&humanoid.physics), x, y, world, &self.protected)
+                                .and_then(|()| {
+                                    check_tile_placement(
+                                        x,
+                                        y,
+                                        placed,
+                                        foreground,
+                                        fg,
+                                        bg,
+                                        &self.humanoids,
+                                    )
+                                })
+                                .map(|()| placed)
+                        }
+                    };
+                    let placed = match check {
+                        Ok(placed) => placed,
+                        Err(reason) => {
+                            println!("[Server] {addr:?} can't place at ({x}, {y}): {reason}.");
+                            connection.net_events.push(update(x, y, tile));
+                            continue;
+                        }
+                    };
+
+                    // Use up the item.
+                    let humanoid = self.humanoids.get_mut(&connection.humanoid_id).unwrap();
+                    humanoid.inventory.take_one(slot);
+                    humanoid.inventory_changed = true;
+                    connection.next_edit_ms = timestamp_ms + EDIT_COOLDOWN_MS;
+
+                    let tiles = match foreground {
+                        true => &mut self.foreground_tiles,
+                        false => &mut self.background_tiles,
+                    };
+                    *tiles.get_mut(x as _, y as _).unwrap() = placed;
+                    partial_broadcast(&mut self.connections, addr, update(x, y, placed));
+                }
                 _ => {}
             }
         }
 
-        // Cull connections if they haven't been heard from in 5 seconds
+        // Cull connections if they haven't been heard from in a while.
+        let timeout_ms = self.timeout_ms;
         self.connections.retain(|addr, connection| {
-            if (timestamp_ms as u16).wrapping_sub(connection.last_msg) < 5_000 {
+            if timestamp_ms.saturating_sub(connection.last_msg) < timeout_ms {
                 return true;
             }
 
@@ -237,10 +503,212 @@ impl GameUpdate {
         });
     }
 
+    /// Returns why a Connect should be refused, if it should be.
+    fn check_connect(
+        &self,
+        addr: SocketAddr,
+        version: u32,
+        build_hash: u64,
+        name: &str,
+    ) -> Option<RejectReason> {
+        if version != PROTOCOL_VERSION || build_hash != content_hash() {
+            return Some(RejectReason::VersionMismatch(
+                PROTOCOL_VERSION,
+                content_hash(),
+            ));
+        }
+        let ip = addr.ip().to_string();
+        if self
+            .banned
+            .iter()
+            .any(|b| *b == ip || b.eq_ignore_ascii_case(name))
+        {
+            return Some(RejectReason::Banned);
+        }
+        if self.connections.len() >= self.max_players {
+            return Some(RejectReason::ServerFull);
+        }
+        if !is_valid_name(name) {
+            return Some(RejectReason::InvalidName);
+        }
+        if self
+            .connections
+            .values()
+            .any(|c| c.name.eq_ignore_ascii_case(name))
+        {
+            return Some(RejectReason::NameTaken);
+        }
+        None
+    }
+
+    /// Runs a command typed into the server console, and returns what to print.
+    pub fn command(&mut self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            [] => String::new(),
+            ["stop"] => {
+                self.kill = true;
+                String::from("Stopping.")
+            }
+            ["time"] => format!("It is {}.", format_time_of_day(self.time_of_day_us)),
+            ["time", time] => match parse_time_of_day(time) {
+                Ok(time) => {
+                    self.time_of_day_us = time;
+                    self.broadcast_time();
+                    format!("Set the time to {}.", format_time_of_day(time))
+                }
+                Err(e) => format!("Could not set the time: {e}."),
+            },
+            _ => format!(
+                "Unknown command {line:?}. Commands: stop, time [HH:MM|dawn|noon|dusk|midnight]."
+            ),
+        }
+    }
+
+    // Sends every connection the time of day.
+    fn broadcast_time(&mut self) {
+        for connection in self.connections.values_mut() {
+            connection
+                .net_events
+                .push(NetEvent::WorldTime(self.time_of_day_us));
+        }
+    }
+
+    /// Breaks a tile, dropping it as an item, and tells everyone.
+    fn break_tile(&mut self, x: u16, y: u16, foreground: bool) {
+        let (tiles, update): (_, fn(u16, u16, Tile) -> NetEvent) = match foreground {
+            true => (&mut self.foreground_tiles, NetEvent::UpdateForegroundTile),
+            false => (&mut self.background_tiles, NetEvent::UpdateBackgroundTile),
+        };
+        let tile = tiles.get_mut(x as _, y as _).unwrap();
+
+        // Drop the tile as an item, tossed up from where it was.
+        if let Some(id) = ItemId::dropped_by(*tile) {
+            let item_id = self.item_id_counter;
+            self.item_id_counter += 1;
+            let r = hash(self.seed, item_id as i64, 0);
+            let spread = (r >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+            let item = Item {
+                id,
+                count: 1,
+                age_us: 0,
+                x: (x as usize * TILE_SIZE) as f32,
+                y: (y as usize * TILE_SIZE) as f32,
+                dx: spread * ITEM_DROP_SPEED,
+                dy: -ITEM_DROP_SPEED,
+            };
+            self.items.insert(item_id, item);
+        }
+
+        *tile = Tile::NONE;
+        for connection in self.connections.values_mut() {
+            connection.net_events.push(update(x, y, Tile::NONE));
+        }
+    }
+
     pub fn step(&mut self, timestamp: u64, frametime: u64) {
         let dt = frametime as f32 / 1_000_000.;
 
+        // Advance the world clock.
+        self.time_of_day_us = (self.time_of_day_us + frametime) % DAY_LENGTH_US;
+
+        // Replay player inputs. Each one covers a fixed slice of time, and a humanoid can't use
+        // more than has passed, so sending extra inputs doesn't speed a player up. A client that
+        // falls too far behind (or stops sending) is stepped with no keys held, so it still falls.
         let mut tmp = vec![];
+        let input_dt = INPUT_FRAMETIME_US as f32 / 1_000_000.;
+        for humanoid in self.humanoids.values_mut() {
+            humanoid.input_budget_us += frametime;
+            while humanoid.input_budget_us >= INPUT_FRAMETIME_US {
+                let input = match humanoid.inputs.pop_front() {
+                    Some(input) => {
+                        humanoid.applied_input_frame = Some(input.frame);
+                        input
+                    }
+                    None if humanoid.input_budget_us > MAX_INPUT_BUDGET_US => {
+                        HumanoidInput::default()
+                    }
+                    None => break,
+                };
+                humanoid.input_budget_us -= INPUT_FRAMETIME_US;
+                let tiles = &self.foreground_tiles;
+                step_humanoid(input_dt, &mut humanoid.physics, &input, tiles, &mut tmp);
+            }
+        }
+
+        // Mining. Targets that can't be mined anymore (moved out of reach, or broken by someone
+        // else) are dropped, and the player told so.
+        let mut broken = vec![];
+        let mut dropped = vec![];
+        let world = (self.world_w, self.world_h);
+        for (&id, humanoid) in self.humanoids.iter_mut() {
+            let Some(mining) = &mut humanoid.mining else {
+                continue;
+            };
+            let (x, y) = (mining.x, mining.y);
+            let tiles = match mining.foreground {
+                true => &self.foreground_tiles,
+                false => &self.background_tiles,
+            };
+            let tile = tiles.get(x as _, y as _).copied().unwrap_or(Tile::NONE);
+            let check = check_tile_edit(Some(&humanoid.physics), x, y, world, &self.protected);
+            if tile == Tile::NONE || check.is_err() {
+                humanoid.mining = None;
+                dropped.push(id);
+                continue;
+            }
+
+            mining.progress_us += frametime;
+            let tool = humanoid.inventory.get(humanoid.selected_slot).map(|s| s.id);
+            if mining.progress_us >= mining_time_us(tile, tool) {
+                broken.push((x, y, mining.foreground));
+                humanoid.mining = None;
+            }
+        }
+        for (x, y, foreground) in broken {
+            self.break_tile(x, y, foreground);
+        }
+        for connection in self.connections.values_mut() {
+            if dropped.contains(&connection.humanoid_id) {
+                connection.net_events.push(NetEvent::StopMining);
+            }
+        }
+
+        // Refill chunk budgets.
+        for connection in self.connections.values_mut() {
+            connection.chunk_budget = (connection.chunk_budget
+                + CHUNK_BYTES_PER_SECOND * frametime / 1_000_000)
+                .min(MAX_CHUNK_BUDGET);
+        }
+
+        // Players pull in nearby items and pick up the ones they touch, if there's room.
+        for humanoid in self.humanoids.values_mut() {
+            let cx = humanoid.physics.x + HUMANOID_WIDTH as f32 / 2.;
+            let cy = humanoid.physics.y + HUMANOID_HEIGHT as f32 / 2.;
+            self.items.retain(|_, item| {
+                if !humanoid.inventory.can_fit(item.id) {
+                    return true;
+                }
+
+                // Pick up.
+                if item_overlaps_humanoid(item, &humanoid.physics) {
+                    item.count = humanoid.inventory.add(item.id, item.count);
+                    humanoid.inventory_changed = true;
+                    return item.count > 0;
+                }
+
+                // Pull in.
+                let dx = cx - (item.x + ITEM_SIZE / 2.);
+                let dy = cy - (item.y + ITEM_SIZE / 2.);
+                let distance = dx.hypot(dy);
+                if distance < ITEM_MAGNET_RADIUS {
+                    item.dx = dx / distance * ITEM_MAGNET_SPEED;
+                    item.dy = dy / distance * ITEM_MAGNET_SPEED;
+                }
+                true
+            });
+        }
+
         for item in self.items.values_mut() {
             let old_y = item.y;
             update_item_physics_y(dt, item, ITEM_GRAVITY);
@@ -255,26 +723,200 @@ impl GameUpdate {
                 &mut tmp,
             );
             resolve_item_tile_collision_y(item, ty, &tmp);
+
+            let old_x = item.x;
+            update_item_physics_x(dt, item, -item.dx * ITEM_DRAG);
+            tmp.clear();
+            let tx = collect_newly_colliding_tiles_x(
+                old_x,
+                item.x,
+                item.y,
+                16.,
+                16.,
+                &self.foreground_tiles,
+                &mut tmp,
+            );
+            resolve_item_tile_collision_x(item, tx, &tmp);
+        }
+
+        // Merge items of the same kind that are close together, into the oldest. The merge radius
+        // is a tile, so only items in neighbouring tile cells need comparing.
+        let cell = |item: &Item| {
+            let size = TILE_SIZE as f32;
+            (
+                (item.x / size).floor() as i32,
+                (item.y / size).floor() as i32,
+            )
+        };
+        let mut cells: HashMap<(i32, i32), Vec<u64>> = HashMap::new();
+        for (&id, item) in &self.items {
+            cells.entry(cell(item)).or_default().push(id);
         }
+        let ids = Vec::from_iter(self.items.keys().copied());
+        let mut nearby = vec![];
+        for a in &ids {
+            let Some(item_a) = self.items.get(a) else {
+                continue;
+            };
+            let (cx, cy) = cell(item_a);
+            nearby.clear();
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    if let Some(ids) = cells.get(&(cx + dx, cy + dy)) {
+                        nearby.extend(ids.iter().copied().filter(|b| b > a));
+                    }
+                }
+            }
+            nearby.sort_unstable();
+            for b in &nearby {
+                let (Some(item_a), Some(item_b)) = (self.items.get(a), self.items.get(b)) else {
+                    continue;
+                };
+                let close = (item_a.x - item_b.x).hypot(item_a.y - item_b.y) < ITEM_MERGE_RADIUS;
+                let fits =
+                    item_a.count as u32 + item_b.count as u32 <= item_a.id.max_stack() as u32;
+                if item_a.id != item_b.id || !close || !fits {
+                    continue;
+                }
+                let item_b = self.items.remove(b).unwrap();
+                let item_a = self.items.get_mut(a).unwrap();
+                item_a.count += item_b.count;
+                item_a.age_us = item_a.age_us.min(item_b.age_us);
+            }
+        }
+
+        // Despawn items that have been left too long.
+        let lifetime_us = self.item_lifetime_us;
+        self.items.retain(|_, item| {
+            item.age_us += frametime;
+            item.age_us < lifetime_us
+        });
     }
 
     pub fn postframe(
         &mut self,
-        _timestamp: u64,
-        send_to: impl Fn(SocketAddr, &Vec<NetEvent>) -> usize,
+        timestamp: u64,
+        mut send_to: impl FnMut(SocketAddr, &Vec<NetEvent>) -> std::io::Result<usize>,
     ) -> bool {
-        // [TODO: This doesn't scale well]
-        for connection in &mut self.connections.values_mut() {
-            // Sync all humanoids with all players.
-            let humanoids =
-                BTreeMap::from_iter(self.humanoids.iter().map(|(k, v)| (*k, v.physics)));
+        // Send each connection the entities around it, as a delta against the last snapshot it
+        // acknowledged. Entities entering or leaving its area spawn or despawn on the client.
+        let snapshot_id = self.next_snapshot_id;
+        self.next_snapshot_id += 1;
+
+        let world_px = (
+            (self.world_w * TILE_SIZE) as f32,
+            (self.world_h * TILE_SIZE) as f32,
+        );
+        let empty = EntityState::default();
+        for connection in self.connections.values_mut() {
+            let Some(humanoid) = self.humanoids.get(&connection.humanoid_id) else {
+                continue;
+            };
+
+            // Entities the client already has get some slack, so they don't flicker at the edge.
+            let area = interest_area(&humanoid.physics, connection.view_size, world_px);
+            let known = connection
+                .snapshots
+                .back()
+                .map_or(&empty, |(_, state)| state);
+            let state = EntityState {
+                humanoids: self
+                    .humanoids
+                    .iter()
+                    .filter(|&(id, h)| {
+                        let known = known.humanoids.contains_key(id);
+                        in_area(area, h.physics.x, h.physics.y, known)
+                    })
+                    .map(|(&id, h)| (id, QuantizedHumanoid::from(&h.physics)))
+                    .collect(),
+                items: self
+                    .items
+                    .iter()
+                    .filter(|&(id, item)| {
+                        let known = known.items.contains_key(id);
+                        in_area(area, item.x, item.y, known)
+                    })
+                    .map(|(&id, item)| (id, QuantizedItem::from(item)))
+                    .collect(),
+            };
+
+            let baseline = connection.acked_snapshot.and_then(|ack| {
+                let snapshot = connection.snapshots.iter().find(|&&(id, _)| id == ack);
+                snapshot.map(|(_, state)| (ack, state))
+            });
+            let (humanoids, items) = state.diff(baseline.map_or(&empty, |(_, state)| state));
+
+            // The player's own humanoid also goes out in full, for reconciling.
+            let delta = SnapshotDelta {
+                id: snapshot_id,
+                baseline: baseline.map(|(id, _)| id),
+                timestamp_us: timestamp,
+                input_ack: humanoid.applied_input_frame,
+                player: Some(humanoid.physics),
+                humanoids,
+                items,
+            };
             connection
                 .net_events
-                .push(NetEvent::HumanoidData(humanoids));
+                .push(NetEvent::Snapshot(Box::new(delta)));
 
-            // Sync all items with all players.
-            let items = self.items.clone();
-            connection.net_events.push(NetEvent::ItemData(items));
+            connection.snapshots.push_back((snapshot_id, state));
+            if connection.snapshots.len() > SNAPSHOT_HISTORY {
+                connection.snapshots.pop_front();
+            }
+        }
+
+        // Answer chunk requests nearest the player first, as many as each budget allows.
+        const CHUNK_SIZE_PX: f32 = (CHUNK_SIZE * TILE_SIZE) as f32;
+        for connection in self.connections.values_mut() {
+            let Some(humanoid) = self.humanoids.get(&connection.humanoid_id) else {
+                continue;
+            };
+            let cx = (humanoid.physics.x / CHUNK_SIZE_PX) as i32;
+            let cy = (humanoid.physics.y / CHUNK_SIZE_PX) as i32;
+            let distance = |&(x, y): &(u16, u16)| (x as i32 - cx).pow(2) + (y as i32 - cy).pow(2);
+
+            // Nearest last, so they can be popped.
+            let requests = &mut connection.chunk_requests;
+            requests.sort_unstable_by_key(|xy| std::cmp::Reverse(distance(xy)));
+
+            let mut chunks = vec![];
+            while let Some(&(x, y)) = requests.last() {
+                let xr = CHUNK_SIZE * x as usize..CHUNK_SIZE * (x as usize + 1);
+                let yr = CHUNK_SIZE * y as usize..CHUNK_SIZE * (y as usize + 1);
+                let fg = self
+                    .foreground_tiles
+                    .clone_sub(xr.clone(), yr.clone())
+                    .unwrap();
+                let bg = self.background_tiles.clone_sub(xr, yr).unwrap();
+                let chunk = CompressedChunk::new(fg.raw(), bg.raw());
+
+                // Out of budget, the rest wait for a later frame.
+                let size = chunk.size() as u64;
+                if size > connection.chunk_budget {
+                    break;
+                }
+                connection.chunk_budget -= size;
+                requests.pop();
+                chunks.push((x, y, chunk));
+            }
+            if !chunks.is_empty() {
+                connection.net_events.push(NetEvent::UpdateChunks(chunks));
+            }
+        }
+
+        // Send players their inventory, if it changed.
+        for connection in self.connections.values_mut() {
+            let Some(humanoid) = self.humanoids.get_mut(&connection.humanoid_id) else {
+                continue;
+            };
+            if humanoid.inventory_changed {
+                humanoid.inventory_changed = false;
+                let inventory = humanoid.inventory.clone();
+                connection
+                    .net_events
+                    .push(NetEvent::UpdateInventory(inventory));
+            }
         }
 
         // Ping all connections.
@@ -282,9 +924,16 @@ impl GameUpdate {
             connection.net_events.push(NetEvent::Ping);
         }
 
+        // Keep everyone's clock in sync.
+        if timestamp >= self.next_time_sync_us {
+            self.broadcast_time();
+            self.next_time_sync_us = timestamp + TIME_SYNC_INTERVAL_US;
+        }
+
         // Net stuff =/
         println!("#############");
         let mut sent = 0;
+        let mut failed = vec![];
         for (&addr, connection) in self.connections.iter_mut() {
             if connection.net_events.len() > 0 {
                 let s: String = format!("{:?}", connection.net_events)
@@ -292,24 +941,249 @@ impl GameUpdate {
                     .take(200)
                     .collect();
                 println!("[server] {s} sent to {addr:?}");
-                sent += send_to(addr, &connection.net_events);
+                match send_to(addr, &connection.net_events) {
+                    Ok(s) => sent += s,
+                    Err(e) => {
+                        println!("[Server] Could not send to {addr:?}: {e}");
+                        failed.push(addr);
+                    }
+                }
                 connection.net_events.clear();
             }
         }
         println!("Total data sent: {sent} bytes");
 
+        // Whoever missed an event is out of sync now, so drop them.
+        for addr in failed {
+            println!("Disconnected {addr:?}.");
+            let connection = self.connections.remove(&addr).unwrap();
+            self.humanoids.remove(&connection.humanoid_id);
+        }
+
+        // Answer refused connects.
+        for (addr, event) in self.rejections.drain(..) {
+            if let Err(e) = send_to(addr, &vec![event]) {
+                println!("[Server] Could not reject {addr:?}: {e}");
+            }
+        }
+
+        // Save periodically, and before shutting down.
+        if self.kill || timestamp >= self.next_autosave_us {
+            self.save();
+            self.next_autosave_us = timestamp + AUTOSAVE_INTERVAL_US;
+        }
+
         return self.kill;
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 struct Humanoid {
     state: HumanoidState,
     physics: HumanoidPhysics,
+
+    // Inputs waiting to be replayed, oldest first.
+    inputs: VecDeque<HumanoidInput>,
+    last_input_frame: Option<u32>,
+    applied_input_frame: Option<u32>, // The last input replayed.
+    input_budget_us: u64,             // Time the humanoid may still be simulated for.
+
+    inventory: Inventory,
+    inventory_changed: bool, // Sent to the owner in postframe.
+    selected_slot: usize,    // The hotbar slot placed from.
+    mining: Option<Mining>,
+}
+
+/// Checks that a player at ``physics`` may edit tile (``x``, ``y``), returning why not otherwise.
+fn check_tile_edit(
+    physics: Option<&HumanoidPhysics>,
+    x: u16,
+    y: u16,
+    (world_w, world_h): (usize, usize),
+    protected: &[(u16, u16, u16, u16)],
+) -> Result<(), &'static str> {
+    let Some(physics) = physics else {
+        return Err("no humanoid");
+    };
+    let (tx, ty) = (x as usize, y as usize);
+    if !can_reach(physics, tx, ty, HUMANOID_REACH + REACH_TOLERANCE_PX) {
+        return Err("out of reach");
+    }
+    if tx < WORLD_BORDER
+        || ty < WORLD_BORDER
+        || tx >= world_w.saturating_sub(WORLD_BORDER)
+        || ty >= world_h.saturating_sub(WORLD_BORDER)
+    {
+        return Err("at the world's edge");
+    }
+    let inside = |&(x1, y1, x2, y2): &(u16, u16, u16, u16)| x1 <= x && x < x2 && y1 <= y && y < y2;
+    if protected.iter().any(inside) {
+        return Err("protected");
+    }
+    Ok(())
+}
+
+/// Returns true if ``item`` overlaps a humanoid at ``physics``.
+fn item_overlaps_humanoid(item: &Item, physics: &HumanoidPhysics) -> bool {
+    item.x < physics.x + HUMANOID_WIDTH as f32
+        && physics.x < item.x + ITEM_SIZE
+        && item.y < physics.y + HUMANOID_HEIGHT as f32
+        && physics.y < item.y + ITEM_SIZE
+}
+
+/// Checks that ``tile`` can be placed at (``x``, ``y``) in the foreground or background, returning
+/// why not otherwise.
+fn check_tile_placement(
+    x: u16,
+    y: u16,
+    tile: Tile,
+    foreground: bool,
+    foreground_tiles: &Array2D<Tile>,
+    background_tiles: &Array2D<Tile>,
+    humanoids: &BTreeMap<u64, Humanoid>,
+) -> Result<(), &'static str> {
+    let (x, y) = (x as usize, y as usize);
+    let tiles = match foreground {
+        true => foreground_tiles,
+        false => background_tiles,
+    };
+    if tiles.get(x, y) != Some(&Tile::NONE) {
+        return Err("occupied");
+    }
+
+    // Tiles are placed against another tile, beside it or in the other layer.
+    let solid = |x: usize, y: usize| {
+        let fg = foreground_tiles
+            .get(x, y)
+            .map_or(false, |&t| t != Tile::NONE);
+        let bg = background_tiles
+            .get(x, y)
+            .map_or(false, |&t| t != Tile::NONE);
+        fg || bg
+    };
+    let neighbors = [
+        (x, y),
+        (x.wrapping_sub(1), y),
+        (x + 1, y),
+        (x, y.wrapping_sub(1)),
+        (x, y + 1),
+    ];
+    if !neighbors.into_iter().any(|(x, y)| solid(x, y)) {
+        return Err("nothing to place against");
+    }
+
+    // Solid foreground tiles can't go where someone is standing.
+    if foreground && tile.is_solid() && humanoids.values().any(|h| overlaps_tile(&h.physics, x, y))
+    {
+        return Err("blocked by a humanoid");
+    }
+    Ok(())
+}
+
+/// Generates a fresh world.
+fn generate_world(
+    generator: &impl WorldGenerator,
+    seed: u64,
+    world_w: usize,
+    world_h: usize,
+) -> WorldSave {
+    let (foreground_tiles, background_tiles) = generator.generate(seed, world_w, world_h);
+    let to_raw = |tiles: Array2D<Tile>| tiles.into_raw().iter().map(|&t| t.0).collect();
+
+    WorldSave {
+        seed,
+        world_w: world_w as u32,
+        world_h: world_h as u32,
+        foreground_tiles: to_raw(foreground_tiles),
+        background_tiles: to_raw(background_tiles),
+        item_id_counter: 0,
+        items: BTreeMap::new(),
+        humanoid_id_counter: 0,
+        time_of_day_us: NEW_WORLD_TIME_US,
+    }
 }
 
 struct Connection {
-    last_msg: u16,
-    humanoid_id: u64, // the ID this connection owns
+    name: String,
+    last_msg: u64,
+    humanoid_id: u64,      // the ID this connection owns
+    view_size: (u16, u16), // The client's window (in pixels).
+    acked_snapshot: Option<u32>,
+    snapshots: VecDeque<(u32, EntityState)>, // Sent to this client, oldest first.
+    chunk_requests: Vec<(u16, u16)>,         // Waiting to be sent.
+    chunk_budget: u64,                       // Bytes of chunks that may be sent now.
+    next_edit_ms: u64,                       // Tile edits before this are refused.
     net_events: Vec<NetEvent>,
 }
+
+/// Returns the area (x1, y1, x2, y2, in pixels) a player at ``physics`` can see, clamped to the
+/// world like the client's camera.
+fn interest_area(
+    physics: &HumanoidPhysics,
+    (view_w, view_h): (u16, u16),
+    (world_w, world_h): (f32, f32),
+) -> (f32, f32, f32, f32) {
+    let (w, h) = (view_w as f32, view_h as f32);
+    let x1 = (physics.x - w / 2.).min(world_w - w).max(0.);
+    let y1 = (physics.y - h / 2.).min(world_h - h).max(0.);
+    (x1, y1, x1 + w, y1 + h)
+}
+
+/// Returns true if an entity at (``x``, ``y``) should be sent to a player seeing ``area``.
+fn in_area((x1, y1, x2, y2): (f32, f32, f32, f32), x: f32, y: f32, known: bool) -> bool {
+    let margin = match known {
+        true => INTEREST_MARGIN_PX + INTEREST_HYSTERESIS_PX,
+        false => INTEREST_MARGIN_PX,
+    };
+    x1 - margin <= x && x <= x2 + margin && y1 - margin <= y && y <= y2 + margin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unloadable_saves_are_not_overwritten() {
+        let dir = std::env::temp_dir().join(format!("duplicarria_save_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A save from a newer version.
+        let save_path = dir.join("world.sav");
+        let mut newer = b"DUPW".to_vec();
+        newer.extend((WORLD_SAVE_VERSION + 1).to_le_bytes());
+        newer.extend([1, 2, 3]);
+        std::fs::write(&save_path, &newer).unwrap();
+
+        let config = ServerConfig {
+            world_w: 2,
+            world_h: 2,
+            seed: Some(1),
+            save_path: save_path.clone(),
+            ..Default::default()
+        };
+        GameUpdate::new(&config).save();
+
+        let kept: Vec<Vec<u8>> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().contains(".bad-"))
+            .map(|path| std::fs::read(path).unwrap())
+            .collect();
+        assert_eq!(kept, vec![newer]);
+        assert_eq!(load_world(&save_path).unwrap().seed, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn same_seed_same_world() {
+        let generator = OverworldGenerator::default();
+        let (w, h) = (8 * CHUNK_SIZE, 4 * CHUNK_SIZE);
+        let a = generate_world(&generator, 42, w, h);
+        let b = generate_world(&generator, 42, w, h);
+        let c = generate_world(&generator, 43, w, h);
+        assert_eq!(a.foreground_tiles, b.foreground_tiles);
+        assert_eq!(a.background_tiles, b.background_tiles);
+        assert_ne!(a.foreground_tiles, c.foreground_tiles);
+    }
+}
@@ -1,46 +1,75 @@
+pub mod config;
 mod game_update;
+mod save;
+mod world_gen;
 
 use std::net::UdpSocket;
 use std::{thread, thread::JoinHandle};
 
+use self::config::*;
 use self::game_update::*;
 use crate::net::*;
 use crate::time::*;
 
-pub fn launch_server(port: u16) -> (u16, JoinHandle<()>) {
+pub fn launch_server(config: ServerConfig) -> Result<(u16, JoinHandle<()>), String> {
     // Create socket.
-    let socket = UdpSocket::bind(("0.0.0.0", port)).unwrap();
+    let address = (config.bind_address.as_str(), config.port);
+    let socket = UdpSocket::bind(address)
+        .map_err(|e| format!("could not bind to {}:{}: {e}", address.0, address.1))?;
     socket.set_nonblocking(true).unwrap();
     let port = socket.local_addr().unwrap().port();
 
     // Spawn server update thread.
     let update_handle = thread::Builder::new()
         .name(String::from("server_update_thread"))
-        .spawn(move || server_update_thread(socket))
+        .spawn(move || server_update_thread(socket, config))
         .unwrap();
 
-    (port, update_handle)
+    Ok((port, update_handle))
 }
 
-pub fn server_update_thread(socket: UdpSocket) {
+pub fn server_update_thread(socket: UdpSocket, config: ServerConfig) {
     println!("[Server] Update thread start.");
-    let frametime = 99_996; // us
+    let frametime = config.frametime_us();
     let mut timestamp = get_microseconds_as_u64();
 
     //
     let mut net_events = vec![];
 
     // Create server state.
-    let mut game_update = GameUpdate::new();
+    let mut game_update = GameUpdate::new(&config);
+    let mut endpoint = Endpoint::new(socket, config.timeout_ms);
+
+    // Read console commands on their own thread, since reading stdin blocks.
+    let (command_send, command_recv) = crossbeam_channel::unbounded();
+    thread::Builder::new()
+        .name(String::from("server_console_thread"))
+        .spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if command_send.send(line).is_err() {
+                    break;
+                }
+            }
+        })
+        .unwrap();
 
     loop {
         // Wait until enough has passed for at least 1 frame
         let next_timestamp = wait(timestamp + frametime);
 
         // Run preframe.
-        recv_from(&socket, &mut net_events);
+        endpoint.recv_from(&mut net_events);
         game_update.preframe(timestamp, std::mem::take(&mut net_events).into_iter());
 
+        // Run console commands.
+        for line in command_recv.try_iter() {
+            let reply = game_update.command(&line);
+            if !reply.is_empty() {
+                println!("[Server] {reply}");
+            }
+        }
+
         // Simulate the time between timestamp and next_timestamp:
         let frames = (next_timestamp - timestamp) / frametime;
         for _ in 0..frames {
@@ -50,7 +79,7 @@ pub fn server_update_thread(socket: UdpSocket) {
 
         // Run postframe.
         use crate::game::net::NetEvent;
-        let send_to_fn = |addr, net_events: &Vec<NetEvent>| send_to(&socket, addr, net_events);
+        let send_to_fn = |addr, net_events: &Vec<NetEvent>| endpoint.send_to(addr, net_events);
         if game_update.postframe(timestamp, send_to_fn) == true {
             break;
         }
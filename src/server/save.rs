@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+
+use crate::game::item::*;
+use crate::game::sky::*;
+
+// World save header.
+const WORLD_SAVE_MAGIC: [u8; 4] = *b"DUPW";
+pub const WORLD_SAVE_VERSION: u32 = 5;
+
+/// Everything about a world that outlives a server run. Tiles are stored as their raw u8 value,
+/// since bincode would otherwise spend 4 bytes on every enum tag.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WorldSave {
+    pub seed: u64,
+    pub world_w: u32,
+    pub world_h: u32,
+    pub foreground_tiles: Vec<u8>,
+    pub background_tiles: Vec<u8>,
+    pub item_id_counter: u64,
+    pub items: BTreeMap<u64, Item>,
+    pub humanoid_id_counter: u64,
+    pub time_of_day_us: u64,
+}
+
+/// Version 1: no seed.
+#[derive(Deserialize)]
+struct WorldSaveV1 {
+    world_w: u32,
+    world_h: u32,
+    foreground_tiles: Vec<u8>,
+    background_tiles: Vec<u8>,
+    item_id_counter: u64,
+    items: BTreeMap<u64, ItemV2>,
+    humanoid_id_counter: u64,
+}
+
+/// Version 2: items have no count or age.
+#[derive(Deserialize)]
+struct WorldSaveV2 {
+    seed: u64,
+    world_w: u32,
+    world_h: u32,
+    foreground_tiles: Vec<u8>,
+    background_tiles: Vec<u8>,
+    item_id_counter: u64,
+    items: BTreeMap<u64, ItemV2>,
+    humanoid_id_counter: u64,
+}
+
+/// Version 3: item ids are a 4 byte enum tag.
+#[derive(Deserialize)]
+struct WorldSaveV3 {
+    seed: u64,
+    world_w: u32,
+    world_h: u32,
+    foreground_tiles: Vec<u8>,
+    background_tiles: Vec<u8>,
+    item_id_counter: u64,
+    items: BTreeMap<u64, ItemV3>,
+    humanoid_id_counter: u64,
+}
+
+/// Version 4: no world clock.
+#[derive(Deserialize)]
+struct WorldSaveV4 {
+    seed: u64,
+    world_w: u32,
+    world_h: u32,
+    foreground_tiles: Vec<u8>,
+    background_tiles: Vec<u8>,
+    item_id_counter: u64,
+    items: BTreeMap<u64, Item>,
+    humanoid_id_counter: u64,
+}
+
+#[derive(Deserialize)]
+struct ItemV2 {
+    id: u32,
+    x: f32,
+    y: f32,
+    dx: f32,
+    dy: f32,
+}
+
+#[derive(Deserialize)]
+struct ItemV3 {
+    id: u32,
+    count: u16,
+    age_us: u64,
+    x: f32,
+    y: f32,
+    dx: f32,
+    dy: f32,
+}
+
+impl ItemV2 {
+    fn migrate(items: BTreeMap<u64, ItemV2>) -> BTreeMap<u64, Item> {
+        let items = items.into_iter().map(|(k, v)| {
+            let item = ItemV3 {
+                id: v.id,
+                count: 1,
+                age_us: 0,
+                x: v.x,
+                y: v.y,
+                dx: v.dx,
+                dy: v.dy,
+            };
+            (k, item)
+        });
+        ItemV3::migrate(items.collect())
+    }
+}
+
+impl ItemV3 {
+    /// Items the registry doesn't know (anymore) are dropped.
+    fn migrate(items: BTreeMap<u64, ItemV3>) -> BTreeMap<u64, Item> {
+        let items = items.into_iter().filter_map(|(k, v)| {
+            let id = u16::try_from(v.id).ok()?;
+            let item = Item {
+                id: ItemId::try_from(id).ok()?,
+                count: v.count,
+                age_us: v.age_us,
+                x: v.x,
+                y: v.y,
+                dx: v.dx,
+                dy: v.dy,
+            };
+            Some((k, item))
+        });
+        items.collect()
+    }
+}
+
+/// Writes a world save to ``path``. The save is written to a temporary file first and then
+/// renamed over the old one, so a crash mid-save can't corrupt the previous save.
+pub fn save_world(path: &Path, save: &WorldSave) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(&WORLD_SAVE_MAGIC)?;
+        writer.write_all(&WORLD_SAVE_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, save).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        writer.flush()?;
+    }
+
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Moves an unreadable save at ``path`` to ``<path>.bad-<unix time>``, so the new world saved in
+/// its place doesn't overwrite it. Returns where it went.
+pub fn set_aside_world(path: &Path) -> Result<PathBuf> {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut bad_path = path.as_os_str().to_owned();
+    bad_path.push(format!(".bad-{time}"));
+    let bad_path = PathBuf::from(bad_path);
+    if bad_path.exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{bad_path:?} exists"),
+        ));
+    }
+    std::fs::rename(path, &bad_path)?;
+    Ok(bad_path)
+}
+
+/// Reads a world save from ``path``.
+pub fn load_world(path: &Path) -> Result<WorldSave> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    // Check header.
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != WORLD_SAVE_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a world save"));
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+
+    // Older versions get migrated here.
+    let map_err = |e| Error::new(ErrorKind::InvalidData, e);
+    let save: WorldSave = match version {
+        1 => {
+            let v1: WorldSaveV1 = bincode::deserialize_from(&mut reader).map_err(map_err)?;
+            WorldSave {
+                seed: 0,
+                world_w: v1.world_w,
+                world_h: v1.world_h,
+                foreground_tiles: v1.foreground_tiles,
+                background_tiles: v1.background_tiles,
+                item_id_counter: v1.item_id_counter,
+                items: ItemV2::migrate(v1.items),
+                humanoid_id_counter: v1.humanoid_id_counter,
+                time_of_day_us: NEW_WORLD_TIME_US,
+            }
+        }
+        2 => {
+            let v2: WorldSaveV2 = bincode::deserialize_from(&mut reader).map_err(map_err)?;
+            WorldSave {
+                seed: v2.seed,
+                world_w: v2.world_w,
+                world_h: v2.world_h,
+                foreground_tiles: v2.foreground_tiles,
+                background_tiles: v2.background_tiles,
+                item_id_counter: v2.item_id_counter,
+                items: ItemV2::migrate(v2.items),
+                humanoid_id_counter: v2.humanoid_id_counter,
+                time_of_day_us: NEW_WORLD_TIME_US,
+            }
+        }
+        3 => {
+            let v3: WorldSaveV3 = bincode::deserialize_from(&mut reader).map_err(map_err)?;
+            WorldSave {
+                seed: v3.seed,
+                world_w: v3.world_w,
+                world_h: v3.world_h,
+                foreground_tiles: v3.foreground_tiles,
+                background_tiles: v3.background_tiles,
+                item_id_counter: v3.item_id_counter,
+                items: ItemV3::migrate(v3.items),
+                humanoid_id_counter: v3.humanoid_id_counter,
+                time_of_day_us: NEW_WORLD_TIME_US,
+            }
+        }
+        4 => {
+            let v4: WorldSaveV4 = bincode::deserialize_from(&mut reader).map_err(map_err)?;
+            WorldSave {
+                seed: v4.seed,
+                world_w: v4.world_w,
+                world_h: v4.world_h,
+                foreground_tiles: v4.foreground_tiles,
+                background_tiles: v4.background_tiles,
+                item_id_counter: v4.item_id_counter,
+                items: v4.items,
+                humanoid_id_counter: v4.humanoid_id_counter,
+                time_of_day_us: NEW_WORLD_TIME_US,
+            }
+        }
+        WORLD_SAVE_VERSION => bincode::deserialize_from(&mut reader).map_err(map_err)?,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported world save version {version}"),
+            ))
+        }
+    };
+
+    // Make sure the tile layers match the world size.
+    let len = save.world_w as usize * save.world_h as usize;
+    if save.foreground_tiles.len() != len || save.background_tiles.len() != len {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "tile layer size mismatch",
+        ));
+    }
+
+    Ok(save)
+}
@@ -0,0 +1,204 @@
+use crate::array2d::*;
+use crate::game::registry::*;
+use crate::game::tile::*;
+
+/// Produces the tile layers of a new world. Implementations must be deterministic: the same seed
+/// and size always give the same world.
+pub trait WorldGenerator {
+    fn generate(&self, seed: u64, w: usize, h: usize) -> (Array2D<Tile>, Array2D<Tile>);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Biome {
+    Forest,
+    Desert,
+    Tundra,
+}
+
+/// The default generator: rolling noise terrain split into surface biomes, with caves and ore
+/// pockets underground.
+#[derive(Clone, Debug)]
+pub struct OverworldGenerator {
+    pub surface_level: f32,     // Average surface height (in tiles from the top).
+    pub surface_amplitude: f32, // Max surface deviation (in tiles).
+    pub dirt_depth: usize,      // Depth of the soil layer (in tiles).
+    pub biome_width: f32,       // Rough width of a biome (in tiles).
+    pub cave_threshold: f32,    // Lower is narrower caves.
+    pub ore_threshold: f32,     // Higher is rarer ore.
+}
+
+impl Default for OverworldGenerator {
+    fn default() -> Self {
+        Self {
+            surface_level: 48.,
+            surface_amplitude: 16.,
+            dirt_depth: 8,
+            biome_width: 256.,
+            cave_threshold: 0.06,
+            ore_threshold: 0.78,
+        }
+    }
+}
+
+impl WorldGenerator for OverworldGenerator {
+    fn generate(&self, seed: u64, w: usize, h: usize) -> (Array2D<Tile>, Array2D<Tile>) {
+        // The tiles this generator uses.
+        let tile = |name| {
+            REGISTRY
+                .tile(name)
+                .unwrap_or_else(|| panic!("world generation needs a {name:?} tile"))
+        };
+        let (grass, sand, snow, dirt, stone) = (
+            tile("grass"),
+            tile("sand"),
+            tile("snow"),
+            tile("dirt"),
+            tile("stone"),
+        );
+        let (copper_ore, iron_ore) = (tile("copper_ore"), tile("iron_ore"));
+
+        // Per column biome and surface height.
+        let biomes: Vec<Biome> = (0..w).map(|x| self.biome_at(seed, x)).collect();
+        let surface: Vec<usize> = (0..w)
+            .map(|x| {
+                let n = fbm_1d(seed ^ SURFACE_SEED, x as f32 / 64., 4) * 2. - 1.;
+                let y = self.surface_level + n * self.surface_amplitude;
+                (y.max(1.) as usize).min(h - 1)
+            })
+            .collect();
+
+        // Solid terrain, used as is for the background.
+        let background_tiles = Array2D::from_closure(w, h, |x, y| {
+            let surface_y = surface[x];
+            if y < surface_y {
+                return Tile::NONE;
+            }
+            let depth = y - surface_y;
+            match biomes[x] {
+                Biome::Forest if depth == 0 => grass,
+                Biome::Desert if depth < self.dirt_depth + 4 => sand,
+                Biome::Tundra if depth < 2 => snow,
+                _ if depth < self.dirt_depth => dirt,
+                _ => stone,
+            }
+        });
+
+        // Carve caves and place ore in the foreground.
+        let foreground_tiles = Array2D::from_closure(w, h, |x, y| {
+            let tile = *background_tiles.get(x, y).unwrap();
+            if tile == Tile::NONE {
+                return tile;
+            }
+            let depth = y - surface[x];
+            let (fx, fy) = (x as f32, y as f32);
+
+            // Caves are the thin bands where a noise field crosses 0.5, which gives long
+            // winding tunnels. They are kept off the very surface.
+            if depth > 4 {
+                let n = fbm_2d(seed ^ CAVE_SEED, fx / 48., fy / 32., 3);
+                if (n - 0.5).abs() < self.cave_threshold {
+                    return Tile::NONE;
+                }
+            }
+
+            // Ore pockets only replace stone. Copper is shallow, iron is deep.
+            if tile == stone {
+                let n = fbm_2d(seed ^ ORE_SEED, fx / 6., fy / 6., 2);
+                if n > self.ore_threshold {
+                    return match depth < 96 {
+                        true => copper_ore,
+                        false => iron_ore,
+                    };
+                }
+            }
+
+            tile
+        });
+
+        (foreground_tiles, background_tiles)
+    }
+}
+
+impl OverworldGenerator {
+    pub fn biome_at(&self, seed: u64, x: usize) -> Biome {
+        let n = value_noise_1d(seed ^ BIOME_SEED, x as f32 / self.biome_width);
+        match n {
+            n if n < 0.4 => Biome::Forest,
+            n if n < 0.7 => Biome::Desert,
+            _ => Biome::Tundra,
+        }
+    }
+}
+
+// Seeds for the independent noise fields.
+const SURFACE_SEED: u64 = 0x5EED_0001;
+const BIOME_SEED: u64 = 0x5EED_0002;
+const CAVE_SEED: u64 = 0x5EED_0003;
+const ORE_SEED: u64 = 0x5EED_0004;
+
+/// Hashes a lattice point into a u64 (splitmix64 finalizer).
+#[inline(always)]
+pub fn hash(seed: u64, x: i64, y: i64) -> u64 {
+    let mut z = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Hashes a lattice point into [0, 1).
+#[inline(always)]
+fn lattice(seed: u64, x: i64, y: i64) -> f32 {
+    (hash(seed, x, y) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+#[inline(always)]
+fn smooth(t: f32) -> f32 {
+    t * t * (3. - 2. * t)
+}
+
+/// Smooth value noise in [0, 1).
+pub fn value_noise_1d(seed: u64, x: f32) -> f32 {
+    let (x0, tx) = (x.floor() as i64, smooth(x - x.floor()));
+    let a = lattice(seed, x0, 0);
+    let b = lattice(seed, x0 + 1, 0);
+    a + (b - a) * tx
+}
+
+/// Smooth value noise in [0, 1).
+pub fn value_noise_2d(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, tx) = (x.floor() as i64, smooth(x - x.floor()));
+    let (y0, ty) = (y.floor() as i64, smooth(y - y.floor()));
+    let a = lattice(seed, x0, y0);
+    let b = lattice(seed, x0 + 1, y0);
+    let c = lattice(seed, x0, y0 + 1);
+    let d = lattice(seed, x0 + 1, y0 + 1);
+    let top = a + (b - a) * tx;
+    let bottom = c + (d - c) * tx;
+    top + (bottom - top) * ty
+}
+
+/// Layered value noise in [0, 1). Each octave doubles the frequency and halves the amplitude.
+pub fn fbm_1d(seed: u64, x: f32, octaves: u32) -> f32 {
+    let (mut sum, mut total, mut amp, mut freq) = (0., 0., 1., 1.);
+    for octave in 0..octaves {
+        sum += amp * value_noise_1d(seed.wrapping_add(octave as u64), x * freq);
+        total += amp;
+        amp *= 0.5;
+        freq *= 2.;
+    }
+    sum / total
+}
+
+/// Layered value noise in [0, 1). Each octave doubles the frequency and halves the amplitude.
+pub fn fbm_2d(seed: u64, x: f32, y: f32, octaves: u32) -> f32 {
+    let (mut sum, mut total, mut amp, mut freq) = (0., 0., 1., 1.);
+    for octave in 0..octaves {
+        sum += amp * value_noise_2d(seed.wrapping_add(octave as u64), x * freq, y * freq);
+        total += amp;
+        amp *= 0.5;
+        freq *= 2.;
+    }
+    sum / total
+}